@@ -1,13 +1,67 @@
+use crate::{id::NodeId, path::Path, span::Span};
+
+/// An expression, the source it was parsed from, and its id.
 #[derive(PartialEq, Clone, Debug)]
-#[allow(dead_code)]
-pub enum Expression {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub span: Span,
+    #[cfg_attr(feature = "serde", serde(default = "NodeId::dummy"))]
+    pub id: NodeId,
+}
+
+impl Expression {
+    /// An expression that was not parsed from source.
+    pub fn dummy(kind: ExpressionKind) -> Self {
+        Expression {
+            kind,
+            span: Span::default(),
+            id: NodeId::DUMMY,
+        }
+    }
+}
+
+impl From<ExpressionKind> for Expression {
+    fn from(kind: ExpressionKind) -> Self {
+        Expression::dummy(kind)
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExpressionKind {
     Literal(f64),
     Variable(String),
+    Unary(String, Box<Expression>),
     Binary(String, Box<Expression>, Box<Expression>),
-    Call(String, Vec<Expression>),
+    /// `f(a, b)` or, calling a function of a module, `math::f(a, b)`.
+    Call(Path, Vec<Expression>),
+    /// `{ a; b; c }`, evaluating to its last expression, or 0 when empty.
+    Block(Vec<Expression>),
+    /// `if cond { a } else { b }`. `else if` chains nest in `else_block_expr`,
+    /// a missing `else` evaluates to 0.
     Conditional {
         cond_expr: Box<Expression>,
         if_block_expr: Box<Expression>,
-        else_block_expr: Box<Expression>,
+        else_block_expr: Option<Box<Expression>>,
+    },
+    /// `var a = 1, b in body`, binding mutable locals for the duration of `body`.
+    VarIn {
+        variables: Vec<(String, Option<Expression>)>,
+        body: Box<Expression>,
+    },
+    /// `for var_name = start, cond, step { body }`, evaluating to 0.
+    For {
+        var_name: String,
+        start: Box<Expression>,
+        cond: Box<Expression>,
+        step: Option<Box<Expression>>,
+        body: Box<Expression>,
+    },
+    /// `while cond { body }`, evaluating to 0.
+    While {
+        cond: Box<Expression>,
+        body: Box<Expression>,
     },
 }
@@ -0,0 +1,142 @@
+use crate::{
+    expr::{Expression, ExpressionKind},
+    function::{Function, Prototype},
+    node::{ASTNode, Import, Module},
+};
+
+/// Rewrites the AST by value, turning each node into a new one.
+///
+/// Every method defaults to the `walk_*` function of this module, folding
+/// the node's children and keeping the node itself, so a fold only
+/// overrides the nodes it rewrites.
+pub trait Fold {
+    fn fold_node(&mut self, node: ASTNode) -> ASTNode {
+        walk_node(self, node)
+    }
+
+    fn fold_function(&mut self, function: Function) -> Function {
+        walk_function(self, function)
+    }
+
+    fn fold_prototype(&mut self, prototype: Prototype) -> Prototype {
+        prototype
+    }
+
+    fn fold_import(&mut self, import: Import) -> Import {
+        import
+    }
+
+    fn fold_module(&mut self, module: Module) -> Module {
+        walk_module(self, module)
+    }
+
+    fn fold_expr(&mut self, expr: Expression) -> Expression {
+        walk_expr(self, expr)
+    }
+}
+
+pub fn walk_node<F: Fold + ?Sized>(folder: &mut F, node: ASTNode) -> ASTNode {
+    match node {
+        ASTNode::ExternNode(prototype) => ASTNode::ExternNode(folder.fold_prototype(prototype)),
+        ASTNode::FunctionNode(function) => ASTNode::FunctionNode(folder.fold_function(function)),
+        ASTNode::ImportNode(import) => ASTNode::ImportNode(folder.fold_import(import)),
+        ASTNode::ModuleNode(module) => ASTNode::ModuleNode(folder.fold_module(module)),
+    }
+}
+
+pub fn walk_function<F: Fold + ?Sized>(folder: &mut F, function: Function) -> Function {
+    let Function {
+        is_public,
+        prototype,
+        body,
+        span,
+        id,
+    } = function;
+    Function {
+        is_public,
+        prototype: folder.fold_prototype(prototype),
+        body: body.map(|body| folder.fold_expr(body)),
+        span,
+        id,
+    }
+}
+
+pub fn walk_module<F: Fold + ?Sized>(folder: &mut F, module: Module) -> Module {
+    let Module {
+        name,
+        items,
+        span,
+        id,
+    } = module;
+    Module {
+        name,
+        items: items
+            .into_iter()
+            .map(|item| folder.fold_node(item))
+            .collect(),
+        span,
+        id,
+    }
+}
+
+/// Folds the subexpressions of `expr`, in source order.
+pub fn walk_expr<F: Fold + ?Sized>(folder: &mut F, expr: Expression) -> Expression {
+    let kind = match expr.kind {
+        kind @ (ExpressionKind::Literal(_) | ExpressionKind::Variable(_)) => kind,
+        ExpressionKind::Unary(op, operand) => ExpressionKind::Unary(op, fold_box(folder, operand)),
+        ExpressionKind::Binary(op, lhs, rhs) => {
+            ExpressionKind::Binary(op, fold_box(folder, lhs), fold_box(folder, rhs))
+        }
+        ExpressionKind::Call(path, args) => ExpressionKind::Call(
+            path,
+            args.into_iter().map(|arg| folder.fold_expr(arg)).collect(),
+        ),
+        ExpressionKind::Block(exprs) => {
+            ExpressionKind::Block(exprs.into_iter().map(|e| folder.fold_expr(e)).collect())
+        }
+        ExpressionKind::Conditional {
+            cond_expr,
+            if_block_expr,
+            else_block_expr,
+        } => ExpressionKind::Conditional {
+            cond_expr: fold_box(folder, cond_expr),
+            if_block_expr: fold_box(folder, if_block_expr),
+            else_block_expr: else_block_expr.map(|e| fold_box(folder, e)),
+        },
+        ExpressionKind::VarIn { variables, body } => ExpressionKind::VarIn {
+            variables: variables
+                .into_iter()
+                .map(|(name, initializer)| (name, initializer.map(|e| folder.fold_expr(e))))
+                .collect(),
+            body: fold_box(folder, body),
+        },
+        ExpressionKind::For {
+            var_name,
+            start,
+            cond,
+            step,
+            body,
+        } => ExpressionKind::For {
+            var_name,
+            start: fold_box(folder, start),
+            cond: fold_box(folder, cond),
+            step: step.map(|step| fold_box(folder, step)),
+            body: fold_box(folder, body),
+        },
+        ExpressionKind::While { cond, body } => ExpressionKind::While {
+            cond: fold_box(folder, cond),
+            body: fold_box(folder, body),
+        },
+    };
+    Expression {
+        kind,
+        span: expr.span,
+        id: expr.id,
+    }
+}
+
+/// Folds a boxed subexpression, reusing its box.
+fn fold_box<F: Fold + ?Sized>(folder: &mut F, mut expr: Box<Expression>) -> Box<Expression> {
+    *expr = folder.fold_expr(*expr);
+    expr
+}
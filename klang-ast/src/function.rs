@@ -1,13 +1,49 @@
-use crate::expr::Expression;
+use crate::{expr::Expression, id::NodeId, span::Span};
 
 #[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Function {
+    /// Declared `pub`, so callable from outside the module declaring it.
+    pub is_public: bool,
     pub prototype: Prototype,
     pub body: Option<Expression>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub span: Span,
+    #[cfg_attr(feature = "serde", serde(default = "NodeId::dummy"))]
+    pub id: NodeId,
 }
 
 #[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Prototype {
     pub name: String,
     pub args: Vec<String>,
+    pub kind: PrototypeKind,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub span: Span,
+    #[cfg_attr(feature = "serde", serde(default = "NodeId::dummy"))]
+    pub id: NodeId,
+}
+
+/// Distinguishes plain functions from user-defined operators.
+///
+/// Operator prototypes are named after the operator they define, e.g. a
+/// `fun binary| 5 (a, b)` declaration is called `binary|`.
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PrototypeKind {
+    Function,
+    UnaryOperator,
+    BinaryOperator { precedence: i32 },
+}
+
+impl Prototype {
+    /// Returns the operator symbol if this prototype defines an operator.
+    pub fn operator(&self) -> Option<&str> {
+        match self.kind {
+            PrototypeKind::Function => None,
+            PrototypeKind::UnaryOperator => self.name.strip_prefix("unary"),
+            PrototypeKind::BinaryOperator { .. } => self.name.strip_prefix("binary"),
+        }
+    }
 }
@@ -0,0 +1,24 @@
+/// Identifies a node of the AST.
+///
+/// The parser numbers nodes in the order it completes them, so parsing the
+/// same source again gives the same ids.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Default, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeId(pub u32);
+
+impl NodeId {
+    /// Id of nodes that were not parsed from source.
+    pub const DUMMY: NodeId = NodeId(u32::MAX);
+
+    /// `DUMMY`, for the nodes deserialized without an id, like those built by
+    /// other frontends.
+    #[cfg(feature = "serde")]
+    pub(crate) fn dummy() -> NodeId {
+        NodeId::DUMMY
+    }
+
+    /// The id following this one.
+    pub fn next(self) -> NodeId {
+        NodeId(self.0 + 1)
+    }
+}
@@ -1,3 +1,12 @@
 pub mod expr;
+pub mod fold;
 pub mod function;
+pub mod id;
 pub mod node;
+pub mod op;
+pub mod path;
+pub mod print;
+pub mod sexpr;
+pub mod span;
+pub mod spanless;
+pub mod visit;
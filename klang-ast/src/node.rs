@@ -1,8 +1,57 @@
-use crate::function::{Function, Prototype};
+use crate::{
+    function::{Function, Prototype},
+    id::NodeId,
+    span::Span,
+};
 
 #[derive(PartialEq, Clone, Debug)]
-#[allow(dead_code)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ASTNode {
     ExternNode(Prototype),
     FunctionNode(Function),
+    ImportNode(Import),
+    ModuleNode(Module),
+}
+
+impl ASTNode {
+    pub fn span(&self) -> Span {
+        match self {
+            ASTNode::ExternNode(prototype) => prototype.span,
+            ASTNode::FunctionNode(function) => function.span,
+            ASTNode::ImportNode(import) => import.span,
+            ASTNode::ModuleNode(module) => module.span,
+        }
+    }
+
+    pub fn id(&self) -> NodeId {
+        match self {
+            ASTNode::ExternNode(prototype) => prototype.id,
+            ASTNode::FunctionNode(function) => function.id,
+            ASTNode::ImportNode(import) => import.id,
+            ASTNode::ModuleNode(module) => module.id,
+        }
+    }
+}
+
+/// `import "path"`, the path being relative to the importing file.
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Import {
+    pub path: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub span: Span,
+    #[cfg_attr(feature = "serde", serde(default = "NodeId::dummy"))]
+    pub id: NodeId,
+}
+
+/// `mod name { items }`. Outside of it, its items are named `name::item`.
+#[derive(PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Module {
+    pub name: String,
+    pub items: Vec<ASTNode>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub span: Span,
+    #[cfg_attr(feature = "serde", serde(default = "NodeId::dummy"))]
+    pub id: NodeId,
 }
@@ -0,0 +1,45 @@
+/// How operators of the same precedence group.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Associativity {
+    /// `a - b - c` parses as `(a - b) - c`.
+    Left,
+    /// `a = b = c` parses as `a = (b = c)`.
+    Right,
+}
+
+/// Parsing rules of a binary operator.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct BinaryOperator {
+    pub precedence: i32,
+    pub associativity: Associativity,
+}
+
+impl BinaryOperator {
+    /// Whether this operator, written left of `next`, takes the operand
+    /// between them as its right operand, instead of `next` taking it as its
+    /// left one.
+    pub fn binds_before(&self, next: &BinaryOperator) -> bool {
+        self.precedence > next.precedence
+            || (self.precedence == next.precedence && next.associativity == Associativity::Left)
+    }
+}
+
+/// Binary operators known without being declared, and their precedence.
+pub const BUILTIN_BINARY_OPERATORS: [(&str, i32, Associativity); 13] = [
+    ("=", 2, Associativity::Right),
+    ("||", 5, Associativity::Left),
+    ("&&", 6, Associativity::Left),
+    ("==", 9, Associativity::Left),
+    ("!=", 9, Associativity::Left),
+    ("<", 10, Associativity::Left),
+    (">", 10, Associativity::Left),
+    ("<=", 10, Associativity::Left),
+    (">=", 10, Associativity::Left),
+    ("+", 20, Associativity::Left),
+    ("-", 20, Associativity::Left),
+    ("*", 40, Associativity::Left),
+    ("/", 40, Associativity::Left),
+];
+
+/// Prefix operators known without being declared, and their precedence.
+pub const BUILTIN_UNARY_OPERATORS: [(&str, i32); 2] = [("-", 50), ("!", 50)];
@@ -0,0 +1,37 @@
+use std::fmt;
+
+/// A name qualified by the modules it is declared in, like `math::sq`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Path {
+    pub segments: Vec<String>,
+}
+
+impl Path {
+    /// The name the path ends with, `sq` for `math::sq`.
+    pub fn name(&self) -> &str {
+        self.segments.last().map_or("", String::as_str)
+    }
+
+    /// The modules leading to the name, `["math"]` for `math::sq`.
+    pub fn modules(&self) -> &[String] {
+        self.segments
+            .split_last()
+            .map_or(&[], |(_, modules)| modules)
+    }
+}
+
+impl From<&str> for Path {
+    /// Splits `path` at each `::`.
+    fn from(path: &str) -> Self {
+        Path {
+            segments: path.split("::").map(str::to_string).collect(),
+        }
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.segments.join("::"))
+    }
+}
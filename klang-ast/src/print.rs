@@ -0,0 +1,423 @@
+use std::{collections::HashMap, fmt, fmt::Write, slice};
+
+use crate::{
+    expr::{Expression, ExpressionKind},
+    function::{Function, Prototype, PrototypeKind},
+    node::{ASTNode, Module},
+    op::{Associativity, BinaryOperator, BUILTIN_BINARY_OPERATORS, BUILTIN_UNARY_OPERATORS},
+};
+
+/// Prints AST nodes back to canonically formatted klang source.
+///
+/// Parentheses are only written where the precedence of the operators
+/// requires them, so parsing the printed source with the same operators
+/// gives back the printed nodes, spans and ids aside. Only expressions the
+/// grammar can express round-trip: blocks of more than one expression
+/// outside of braces and negative literals have no source form.
+#[derive(Clone, Debug)]
+pub struct Printer {
+    binary_operators: HashMap<String, BinaryOperator>,
+    unary_operators: HashMap<String, i32>,
+    indent: usize,
+    out: String,
+}
+
+/// An operator left of the expression being printed, still waiting for its
+/// right operand.
+#[derive(Clone, Copy)]
+enum Pending {
+    /// A prefix operator and its precedence, if it has one.
+    Unary(Option<i32>),
+    /// A binary operator, `None` if the printer doesn't know it.
+    Binary(Option<BinaryOperator>),
+}
+
+/// A binary operator right of the expression being printed, `None` if the
+/// printer doesn't know it.
+type Following = Option<BinaryOperator>;
+
+impl Default for Printer {
+    fn default() -> Self {
+        let binary_operators = BUILTIN_BINARY_OPERATORS
+            .iter()
+            .map(|&(op, precedence, associativity)| {
+                let operator = BinaryOperator {
+                    precedence,
+                    associativity,
+                };
+                (op.to_string(), operator)
+            })
+            .collect();
+        let unary_operators = BUILTIN_UNARY_OPERATORS
+            .iter()
+            .map(|&(op, precedence)| (op.to_string(), precedence))
+            .collect();
+        Printer::with_operators(binary_operators, unary_operators)
+    }
+}
+
+impl Printer {
+    /// A printer knowing the given operators instead of the builtin ones,
+    /// like a parser configured with them.
+    pub fn with_operators(
+        binary_operators: HashMap<String, BinaryOperator>,
+        unary_operators: HashMap<String, i32>,
+    ) -> Self {
+        Printer {
+            binary_operators,
+            unary_operators,
+            indent: 0,
+            out: String::new(),
+        }
+    }
+
+    /// Prints `items` as the lines of a source file.
+    ///
+    /// Binary operators the items declare are known to the items after
+    /// them, as they are to the parser.
+    pub fn print_items(mut self, items: &[ASTNode]) -> String {
+        self.items(items);
+        if !items.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+
+    pub fn print_expr(mut self, expr: &Expression) -> String {
+        self.expr(expr, None, None);
+        self.out
+    }
+
+    fn items(&mut self, items: &[ASTNode]) {
+        let mut previous = None;
+        for item in items {
+            if let Some(previous) = previous {
+                self.out.push('\n');
+                if !same_group(previous, item) {
+                    self.out.push('\n');
+                }
+            }
+            self.write_indent();
+            self.item(item);
+            previous = Some(item);
+        }
+    }
+
+    fn item(&mut self, item: &ASTNode) {
+        match item {
+            ASTNode::ExternNode(prototype) => {
+                self.out.push_str("use ");
+                self.prototype(prototype);
+                self.out.push(';');
+            }
+            ASTNode::FunctionNode(Function {
+                prototype,
+                body: Some(body),
+                ..
+            }) if prototype.name.is_empty() => {
+                self.expr(body, None, None);
+                self.out.push(';');
+            }
+            ASTNode::FunctionNode(function) => {
+                if function.is_public {
+                    self.out.push_str("pub ");
+                }
+                match &function.body {
+                    Some(body) => {
+                        self.out.push_str("fun ");
+                        self.prototype(&function.prototype);
+                        self.out.push(' ');
+                        self.braced(body, true);
+                    }
+                    None => {
+                        self.out.push_str("use ");
+                        self.prototype(&function.prototype);
+                        self.out.push(';');
+                    }
+                }
+            }
+            ASTNode::ImportNode(import) => {
+                let _ = write!(self.out, "import \"{}\";", import.path);
+            }
+            ASTNode::ModuleNode(Module { name, items, .. }) => {
+                let _ = write!(self.out, "mod {name} {{");
+                if !items.is_empty() {
+                    self.out.push('\n');
+                    self.indent += 1;
+                    self.items(items);
+                    self.indent -= 1;
+                    self.out.push('\n');
+                    self.write_indent();
+                }
+                self.out.push('}');
+            }
+        }
+        self.register_operator(item);
+    }
+
+    fn prototype(&mut self, prototype: &Prototype) {
+        self.out.push_str(&prototype.name);
+        if let PrototypeKind::BinaryOperator { precedence } = prototype.kind {
+            let _ = write!(self.out, " {precedence} ");
+        }
+        let _ = write!(self.out, "({})", prototype.args.join(", "));
+    }
+
+    /// Learns the binary operator `item` declares, if any.
+    fn register_operator(&mut self, item: &ASTNode) {
+        let prototype = match item {
+            ASTNode::ExternNode(prototype) => prototype,
+            ASTNode::FunctionNode(function) => &function.prototype,
+            ASTNode::ImportNode(_) | ASTNode::ModuleNode(_) => return,
+        };
+        if let (PrototypeKind::BinaryOperator { precedence }, Some(op)) =
+            (&prototype.kind, prototype.operator())
+        {
+            let operator = BinaryOperator {
+                precedence: *precedence,
+                associativity: Associativity::Left,
+            };
+            self.binary_operators.insert(op.to_string(), operator);
+        }
+    }
+
+    /// Prints `expr`, in parentheses if `pending` or `following` would take
+    /// part of it as their operand.
+    fn expr(&mut self, expr: &Expression, pending: Option<Pending>, following: Option<Following>) {
+        if self.needs_parentheses(expr, pending, following) {
+            self.out.push('(');
+            self.expr(expr, None, None);
+            self.out.push(')');
+            return;
+        }
+
+        match &expr.kind {
+            ExpressionKind::Literal(value) => {
+                let _ = write!(self.out, "{value}");
+            }
+            ExpressionKind::Variable(name) => self.out.push_str(name),
+            ExpressionKind::Unary(op, operand) => {
+                self.out.push_str(op);
+                let operand_start = self.out.len();
+                let pending = Some(Pending::Unary(self.unary_operator(op)));
+                self.expr(operand, pending, following);
+                // keep prefix operators apart, they could lex as one
+                if self.out[operand_start..]
+                    .starts_with(|ch: char| ch.is_ascii_punctuation() && ch != '(')
+                {
+                    self.out.insert(operand_start, ' ');
+                }
+            }
+            ExpressionKind::Binary(op, lhs, rhs) => {
+                let operator = self.binary_operator(op);
+                self.expr(lhs, pending, Some(operator));
+                let _ = write!(self.out, " {op} ");
+                self.expr(rhs, Some(Pending::Binary(operator)), following);
+            }
+            ExpressionKind::Call(path, args) => {
+                let _ = write!(self.out, "{path}(");
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expr(arg, None, None);
+                }
+                self.out.push(')');
+            }
+            ExpressionKind::Block(_) => self.braced(expr, false),
+            ExpressionKind::Conditional {
+                cond_expr,
+                if_block_expr,
+                else_block_expr,
+            } => {
+                self.out.push_str("if ");
+                self.expr(cond_expr, None, None);
+                self.out.push(' ');
+                self.braced(if_block_expr, false);
+                match else_block_expr.as_deref() {
+                    Some(
+                        else_if @ Expression {
+                            kind: ExpressionKind::Conditional { .. },
+                            ..
+                        },
+                    ) => {
+                        self.out.push_str(" else ");
+                        self.expr(else_if, None, None);
+                    }
+                    Some(else_block_expr) => {
+                        self.out.push_str(" else ");
+                        self.braced(else_block_expr, false);
+                    }
+                    None => {}
+                }
+            }
+            ExpressionKind::VarIn { variables, body } => {
+                self.out.push_str("var ");
+                for (i, (name, initializer)) in variables.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.out.push_str(name);
+                    if let Some(initializer) = initializer {
+                        self.out.push_str(" = ");
+                        self.expr(initializer, None, None);
+                    }
+                }
+                self.out.push_str(" in ");
+                self.expr(body, None, None);
+            }
+            ExpressionKind::For {
+                var_name,
+                start,
+                cond,
+                step,
+                body,
+            } => {
+                let _ = write!(self.out, "for {var_name} = ");
+                self.expr(start, None, None);
+                self.out.push_str(", ");
+                self.expr(cond, None, None);
+                if let Some(step) = step {
+                    self.out.push_str(", ");
+                    self.expr(step, None, None);
+                }
+                self.out.push(' ');
+                self.braced(body, false);
+            }
+            ExpressionKind::While { cond, body } => {
+                self.out.push_str("while ");
+                self.expr(cond, None, None);
+                self.out.push(' ');
+                self.braced(body, false);
+            }
+        }
+    }
+
+    /// Whether `expr` has to be put in parentheses between `pending` and
+    /// `following`, decided the way the parser groups operators.
+    ///
+    /// Unknown operators are assumed to need parentheses.
+    fn needs_parentheses(
+        &self,
+        expr: &Expression,
+        pending: Option<Pending>,
+        following: Option<Following>,
+    ) -> bool {
+        match &expr.kind {
+            ExpressionKind::Binary(op, ..) => {
+                let operator = self.binary_operator(op);
+                let taken_by_pending =
+                    pending.is_some_and(|pending| binds_before(pending, operator).unwrap_or(true));
+                let taken_by_following = following.is_some_and(|following| {
+                    !binds_before(Pending::Binary(operator), following).unwrap_or(false)
+                });
+                taken_by_pending || taken_by_following
+            }
+            ExpressionKind::Unary(op, _) => {
+                let pending = Pending::Unary(self.unary_operator(op));
+                following
+                    .is_some_and(|following| !binds_before(pending, following).unwrap_or(false))
+            }
+            // the body of a `var` expression extends as far as it can
+            ExpressionKind::VarIn { .. } => following.is_some(),
+            _ => false,
+        }
+    }
+
+    /// Prints `body` in braces, keeping a single short expression on the
+    /// line of the braces unless `multiline` is set.
+    fn braced(&mut self, body: &Expression, multiline: bool) {
+        let exprs = match &body.kind {
+            ExpressionKind::Block(exprs) => exprs.as_slice(),
+            _ => slice::from_ref(body),
+        };
+        if exprs.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+
+        self.indent += 1;
+        let lines: Vec<String> = exprs
+            .iter()
+            .map(|expr| {
+                let outer = std::mem::take(&mut self.out);
+                self.expr(expr, None, None);
+                std::mem::replace(&mut self.out, outer)
+            })
+            .collect();
+
+        if let [line] = &lines[..] {
+            if !multiline && !line.contains('\n') {
+                self.indent -= 1;
+                let _ = write!(self.out, "{{ {line} }}");
+                return;
+            }
+        }
+
+        self.out.push('{');
+        for (i, line) in lines.iter().enumerate() {
+            self.out.push('\n');
+            self.write_indent();
+            self.out.push_str(line);
+            if i + 1 < lines.len() {
+                self.out.push(';');
+            }
+        }
+        self.indent -= 1;
+        self.out.push('\n');
+        self.write_indent();
+        self.out.push('}');
+    }
+
+    fn write_indent(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+    }
+
+    fn binary_operator(&self, op: &str) -> Option<BinaryOperator> {
+        self.binary_operators.get(op).copied()
+    }
+
+    fn unary_operator(&self, op: &str) -> Option<i32> {
+        self.unary_operators.get(op).copied()
+    }
+}
+
+/// Whether `pending` takes the operand before `following` as its right
+/// operand, `None` if that depends on an unknown operator.
+fn binds_before(pending: Pending, following: Following) -> Option<bool> {
+    match pending {
+        // prefix operators without precedence apply to the closest operand
+        Pending::Unary(None) => Some(true),
+        Pending::Unary(Some(precedence)) => {
+            following.map(|following| precedence >= following.precedence)
+        }
+        Pending::Binary(pending) => Some(pending?.binds_before(&following?)),
+    }
+}
+
+/// Whether `previous` and `next` are printed on adjacent lines, without a
+/// blank line between them.
+fn same_group(previous: &ASTNode, next: &ASTNode) -> bool {
+    let is_expr = |item: &ASTNode| matches!(item, ASTNode::FunctionNode(function) if function.prototype.name.is_empty());
+    match (previous, next) {
+        (ASTNode::ImportNode(_), ASTNode::ImportNode(_))
+        | (ASTNode::ExternNode(_), ASTNode::ExternNode(_)) => true,
+        _ => is_expr(previous) && is_expr(next),
+    }
+}
+
+impl fmt::Display for ASTNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut printer = Printer::default();
+        printer.item(self);
+        f.write_str(&printer.out)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&Printer::default().print_expr(self))
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+
+use crate::{
+    expr::{Expression, ExpressionKind},
+    function::{Prototype, PrototypeKind},
+    node::ASTNode,
+};
+
+/// Displays AST nodes as s-expressions, one item per line.
+///
+/// Every node is written as a list headed by its kind, like `(+ a (* b c))`
+/// or `(fun sq (x) (* x x))`, showing how the source was grouped without
+/// the precedence rules of its operators. Spans and ids are left out.
+pub struct SExpr<'a, T: ?Sized>(pub &'a T);
+
+impl fmt::Display for SExpr<'_, [ASTNode]> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in self.0 {
+            writeln!(f, "{}", SExpr(item))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SExpr<'_, ASTNode> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            ASTNode::ExternNode(prototype) => write!(f, "(extern {})", SExpr(prototype)),
+            ASTNode::FunctionNode(function) => {
+                let visibility = if function.is_public { "pub " } else { "" };
+                write!(f, "({visibility}fun {}", SExpr(&function.prototype))?;
+                if let Some(body) = &function.body {
+                    write!(f, " {}", SExpr(body))?;
+                }
+                write!(f, ")")
+            }
+            ASTNode::ImportNode(import) => write!(f, "(import {:?})", import.path),
+            ASTNode::ModuleNode(module) => {
+                write!(f, "(mod {}", module.name)?;
+                for item in &module.items {
+                    write!(f, " {}", SExpr(item))?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// The name and parameters of a prototype, `binary| 5 (a b)` for operators
+/// with a precedence, and just `()` for the unnamed functions wrapping
+/// top-level expressions.
+impl fmt::Display for SExpr<'_, Prototype> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prototype = self.0;
+        if !prototype.name.is_empty() {
+            write!(f, "{} ", prototype.name)?;
+        }
+        if let PrototypeKind::BinaryOperator { precedence } = prototype.kind {
+            write!(f, "{precedence} ")?;
+        }
+        write!(f, "({})", prototype.args.join(" "))
+    }
+}
+
+impl fmt::Display for SExpr<'_, Expression> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0.kind {
+            ExpressionKind::Literal(value) => write!(f, "{value}"),
+            ExpressionKind::Variable(name) => write!(f, "{name}"),
+            ExpressionKind::Unary(op, operand) => write!(f, "({op} {})", SExpr(&**operand)),
+            ExpressionKind::Binary(op, lhs, rhs) => {
+                write!(f, "({op} {} {})", SExpr(&**lhs), SExpr(&**rhs))
+            }
+            ExpressionKind::Call(path, args) => {
+                write!(f, "(call {path}")?;
+                for arg in args {
+                    write!(f, " {}", SExpr(arg))?;
+                }
+                write!(f, ")")
+            }
+            ExpressionKind::Block(exprs) => {
+                write!(f, "(block")?;
+                for expr in exprs {
+                    write!(f, " {}", SExpr(expr))?;
+                }
+                write!(f, ")")
+            }
+            ExpressionKind::Conditional {
+                cond_expr,
+                if_block_expr,
+                else_block_expr,
+            } => {
+                write!(f, "(if {} {}", SExpr(&**cond_expr), SExpr(&**if_block_expr))?;
+                if let Some(else_block_expr) = else_block_expr {
+                    write!(f, " {}", SExpr(&**else_block_expr))?;
+                }
+                write!(f, ")")
+            }
+            ExpressionKind::VarIn { variables, body } => {
+                write!(f, "(var (")?;
+                for (index, (name, init)) in variables.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, " ")?;
+                    }
+                    match init {
+                        Some(init) => write!(f, "({name} {})", SExpr(init))?,
+                        None => write!(f, "({name})")?,
+                    }
+                }
+                write!(f, ") {})", SExpr(&**body))
+            }
+            ExpressionKind::For {
+                var_name,
+                start,
+                cond,
+                step,
+                body,
+            } => {
+                write!(f, "(for ({var_name} {} {}", SExpr(&**start), SExpr(&**cond))?;
+                if let Some(step) = step {
+                    write!(f, " {}", SExpr(&**step))?;
+                }
+                write!(f, ") {})", SExpr(&**body))
+            }
+            ExpressionKind::While { cond, body } => {
+                write!(f, "(while {} {})", SExpr(&**cond), SExpr(&**body))
+            }
+        }
+    }
+}
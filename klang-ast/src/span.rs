@@ -0,0 +1,37 @@
+use std::fmt;
+
+/// Identifies the source file a `Span` points into.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileId(pub u32);
+
+/// A region of source text.
+///
+/// `start` and `end` are byte offsets into the source, while `line` and
+/// `column` are the 1-based position of `start`, with columns counted in
+/// characters rather than bytes.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub file: FileId,
+    pub start: usize,
+    pub end: usize,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl Span {
+    /// Returns a span covering both `self` and `other`, positioned at `self`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
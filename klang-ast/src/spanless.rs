@@ -0,0 +1,171 @@
+use crate::{
+    expr::{Expression, ExpressionKind},
+    function::{Function, Prototype},
+    node::{ASTNode, Import, Module},
+};
+
+/// Equality ignoring spans and node ids.
+///
+/// Nodes parsed from differently laid out source, or built by hand, are
+/// spanless equal when they mean the same.
+pub trait SpanlessEq {
+    fn spanless_eq(&self, other: &Self) -> bool;
+}
+
+impl<T: SpanlessEq> SpanlessEq for [T] {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().zip(other).all(|(a, b)| a.spanless_eq(b))
+    }
+}
+
+impl<T: SpanlessEq> SpanlessEq for Vec<T> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.as_slice().spanless_eq(other.as_slice())
+    }
+}
+
+impl<T: SpanlessEq> SpanlessEq for Option<T> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Some(a), Some(b)) => a.spanless_eq(b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<T: SpanlessEq + ?Sized> SpanlessEq for Box<T> {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        (**self).spanless_eq(&**other)
+    }
+}
+
+impl<A: SpanlessEq, B: SpanlessEq> SpanlessEq for (A, B) {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.0.spanless_eq(&other.0) && self.1.spanless_eq(&other.1)
+    }
+}
+
+impl SpanlessEq for String {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl SpanlessEq for Expression {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.kind.spanless_eq(&other.kind)
+    }
+}
+
+impl SpanlessEq for ExpressionKind {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        use ExpressionKind::*;
+
+        match (self, other) {
+            (Literal(a), Literal(b)) => a == b,
+            (Variable(a), Variable(b)) => a == b,
+            (Unary(op_a, a), Unary(op_b, b)) => op_a == op_b && a.spanless_eq(b),
+            (Binary(op_a, lhs_a, rhs_a), Binary(op_b, lhs_b, rhs_b)) => {
+                op_a == op_b && lhs_a.spanless_eq(lhs_b) && rhs_a.spanless_eq(rhs_b)
+            }
+            (Call(path_a, args_a), Call(path_b, args_b)) => {
+                path_a == path_b && args_a.spanless_eq(args_b)
+            }
+            (Block(a), Block(b)) => a.spanless_eq(b),
+            (
+                Conditional {
+                    cond_expr: cond_a,
+                    if_block_expr: if_a,
+                    else_block_expr: else_a,
+                },
+                Conditional {
+                    cond_expr: cond_b,
+                    if_block_expr: if_b,
+                    else_block_expr: else_b,
+                },
+            ) => cond_a.spanless_eq(cond_b) && if_a.spanless_eq(if_b) && else_a.spanless_eq(else_b),
+            (
+                VarIn {
+                    variables: variables_a,
+                    body: body_a,
+                },
+                VarIn {
+                    variables: variables_b,
+                    body: body_b,
+                },
+            ) => variables_a.spanless_eq(variables_b) && body_a.spanless_eq(body_b),
+            (
+                For {
+                    var_name: var_a,
+                    start: start_a,
+                    cond: cond_a,
+                    step: step_a,
+                    body: body_a,
+                },
+                For {
+                    var_name: var_b,
+                    start: start_b,
+                    cond: cond_b,
+                    step: step_b,
+                    body: body_b,
+                },
+            ) => {
+                var_a == var_b
+                    && start_a.spanless_eq(start_b)
+                    && cond_a.spanless_eq(cond_b)
+                    && step_a.spanless_eq(step_b)
+                    && body_a.spanless_eq(body_b)
+            }
+            (
+                While {
+                    cond: cond_a,
+                    body: body_a,
+                },
+                While {
+                    cond: cond_b,
+                    body: body_b,
+                },
+            ) => cond_a.spanless_eq(cond_b) && body_a.spanless_eq(body_b),
+            _ => false,
+        }
+    }
+}
+
+impl SpanlessEq for Prototype {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.args == other.args && self.kind == other.kind
+    }
+}
+
+impl SpanlessEq for Function {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.is_public == other.is_public
+            && self.prototype.spanless_eq(&other.prototype)
+            && self.body.spanless_eq(&other.body)
+    }
+}
+
+impl SpanlessEq for Import {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl SpanlessEq for Module {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.items.spanless_eq(&other.items)
+    }
+}
+
+impl SpanlessEq for ASTNode {
+    fn spanless_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ASTNode::ExternNode(a), ASTNode::ExternNode(b)) => a.spanless_eq(b),
+            (ASTNode::FunctionNode(a), ASTNode::FunctionNode(b)) => a.spanless_eq(b),
+            (ASTNode::ImportNode(a), ASTNode::ImportNode(b)) => a.spanless_eq(b),
+            (ASTNode::ModuleNode(a), ASTNode::ModuleNode(b)) => a.spanless_eq(b),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{
+    expr::{Expression, ExpressionKind},
+    function::{Function, Prototype},
+    node::{ASTNode, Import, Module},
+};
+
+/// Walks the AST by reference.
+///
+/// Every method defaults to the `walk_*` function visiting the node's
+/// children, so a visitor only overrides the nodes it is interested in. An
+/// override calls the matching `walk_*` function to keep walking below.
+pub trait Visitor {
+    fn visit_node(&mut self, node: &ASTNode) {
+        walk_node(self, node)
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        walk_function(self, function)
+    }
+
+    fn visit_prototype(&mut self, _prototype: &Prototype) {}
+
+    fn visit_import(&mut self, _import: &Import) {}
+
+    fn visit_module(&mut self, module: &Module) {
+        walk_module(self, module)
+    }
+
+    fn visit_expr(&mut self, expr: &Expression) {
+        walk_expr(self, expr)
+    }
+}
+
+pub fn walk_node<V: Visitor + ?Sized>(visitor: &mut V, node: &ASTNode) {
+    match node {
+        ASTNode::ExternNode(prototype) => visitor.visit_prototype(prototype),
+        ASTNode::FunctionNode(function) => visitor.visit_function(function),
+        ASTNode::ImportNode(import) => visitor.visit_import(import),
+        ASTNode::ModuleNode(module) => visitor.visit_module(module),
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, function: &Function) {
+    let Function {
+        is_public: _,
+        prototype,
+        body,
+        span: _,
+        id: _,
+    } = function;
+    visitor.visit_prototype(prototype);
+    if let Some(body) = body {
+        visitor.visit_expr(body);
+    }
+}
+
+pub fn walk_module<V: Visitor + ?Sized>(visitor: &mut V, module: &Module) {
+    let Module {
+        name: _,
+        items,
+        span: _,
+        id: _,
+    } = module;
+    for item in items {
+        visitor.visit_node(item);
+    }
+}
+
+/// Visits the subexpressions of `expr`, in source order.
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expression) {
+    match &expr.kind {
+        ExpressionKind::Literal(_) | ExpressionKind::Variable(_) => {}
+        ExpressionKind::Unary(_, operand) => visitor.visit_expr(operand),
+        ExpressionKind::Binary(_, lhs, rhs) => {
+            visitor.visit_expr(lhs);
+            visitor.visit_expr(rhs);
+        }
+        ExpressionKind::Call(_, args) => {
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+        ExpressionKind::Block(exprs) => {
+            for expr in exprs {
+                visitor.visit_expr(expr);
+            }
+        }
+        ExpressionKind::Conditional {
+            cond_expr,
+            if_block_expr,
+            else_block_expr,
+        } => {
+            visitor.visit_expr(cond_expr);
+            visitor.visit_expr(if_block_expr);
+            if let Some(else_block_expr) = else_block_expr {
+                visitor.visit_expr(else_block_expr);
+            }
+        }
+        ExpressionKind::VarIn { variables, body } => {
+            for (_, initializer) in variables {
+                if let Some(initializer) = initializer {
+                    visitor.visit_expr(initializer);
+                }
+            }
+            visitor.visit_expr(body);
+        }
+        ExpressionKind::For {
+            var_name: _,
+            start,
+            cond,
+            step,
+            body,
+        } => {
+            visitor.visit_expr(start);
+            visitor.visit_expr(cond);
+            if let Some(step) = step {
+                visitor.visit_expr(step);
+            }
+            visitor.visit_expr(body);
+        }
+        ExpressionKind::While { cond, body } => {
+            visitor.visit_expr(cond);
+            visitor.visit_expr(body);
+        }
+    }
+}
+
+/// Walks the AST by mutable reference, to change it in place.
+///
+/// Works like `Visitor`, with `walk_*_mut` functions visiting the children.
+pub trait VisitorMut {
+    fn visit_node_mut(&mut self, node: &mut ASTNode) {
+        walk_node_mut(self, node)
+    }
+
+    fn visit_function_mut(&mut self, function: &mut Function) {
+        walk_function_mut(self, function)
+    }
+
+    fn visit_prototype_mut(&mut self, _prototype: &mut Prototype) {}
+
+    fn visit_import_mut(&mut self, _import: &mut Import) {}
+
+    fn visit_module_mut(&mut self, module: &mut Module) {
+        walk_module_mut(self, module)
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expression) {
+        walk_expr_mut(self, expr)
+    }
+}
+
+pub fn walk_node_mut<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut ASTNode) {
+    match node {
+        ASTNode::ExternNode(prototype) => visitor.visit_prototype_mut(prototype),
+        ASTNode::FunctionNode(function) => visitor.visit_function_mut(function),
+        ASTNode::ImportNode(import) => visitor.visit_import_mut(import),
+        ASTNode::ModuleNode(module) => visitor.visit_module_mut(module),
+    }
+}
+
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(visitor: &mut V, function: &mut Function) {
+    let Function {
+        is_public: _,
+        prototype,
+        body,
+        span: _,
+        id: _,
+    } = function;
+    visitor.visit_prototype_mut(prototype);
+    if let Some(body) = body {
+        visitor.visit_expr_mut(body);
+    }
+}
+
+pub fn walk_module_mut<V: VisitorMut + ?Sized>(visitor: &mut V, module: &mut Module) {
+    let Module {
+        name: _,
+        items,
+        span: _,
+        id: _,
+    } = module;
+    for item in items {
+        visitor.visit_node_mut(item);
+    }
+}
+
+/// Visits the subexpressions of `expr`, in source order.
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expression) {
+    match &mut expr.kind {
+        ExpressionKind::Literal(_) | ExpressionKind::Variable(_) => {}
+        ExpressionKind::Unary(_, operand) => visitor.visit_expr_mut(operand),
+        ExpressionKind::Binary(_, lhs, rhs) => {
+            visitor.visit_expr_mut(lhs);
+            visitor.visit_expr_mut(rhs);
+        }
+        ExpressionKind::Call(_, args) => {
+            for arg in args {
+                visitor.visit_expr_mut(arg);
+            }
+        }
+        ExpressionKind::Block(exprs) => {
+            for expr in exprs {
+                visitor.visit_expr_mut(expr);
+            }
+        }
+        ExpressionKind::Conditional {
+            cond_expr,
+            if_block_expr,
+            else_block_expr,
+        } => {
+            visitor.visit_expr_mut(cond_expr);
+            visitor.visit_expr_mut(if_block_expr);
+            if let Some(else_block_expr) = else_block_expr {
+                visitor.visit_expr_mut(else_block_expr);
+            }
+        }
+        ExpressionKind::VarIn { variables, body } => {
+            for (_, initializer) in variables {
+                if let Some(initializer) = initializer {
+                    visitor.visit_expr_mut(initializer);
+                }
+            }
+            visitor.visit_expr_mut(body);
+        }
+        ExpressionKind::For {
+            var_name: _,
+            start,
+            cond,
+            step,
+            body,
+        } => {
+            visitor.visit_expr_mut(start);
+            visitor.visit_expr_mut(cond);
+            if let Some(step) = step {
+                visitor.visit_expr_mut(step);
+            }
+            visitor.visit_expr_mut(body);
+        }
+        ExpressionKind::While { cond, body } => {
+            visitor.visit_expr_mut(cond);
+            visitor.visit_expr_mut(body);
+        }
+    }
+}
@@ -1,20 +1,63 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
 use inkwell::{
     builder::Builder,
     context::Context,
-    module::Module,
+    module::{Linkage, Module},
     passes::PassManager,
     types::BasicMetadataTypeEnum,
     values::{BasicMetadataValueEnum, FloatValue, FunctionValue, PointerValue},
     FloatPredicate,
 };
 use klang_ast::{
-    expr::Expression,
-    function::{Function, Prototype},
+    expr::{Expression, ExpressionKind},
+    function::{Function, Prototype, PrototypeKind},
     node::ASTNode,
+    path::Path,
+    span::Span,
 };
 
+/// Prefix of the names given to the functions wrapping top-level expressions,
+/// numbered in source order: `__anon_expr.0`, `__anon_expr.1` and so on.
+pub const ANONYMOUS_FUNCTION_PREFIX: &str = "__anon_expr.";
+
+/// An error found while compiling a function.
+#[derive(Debug)]
+pub struct CompileError {
+    pub message: String,
+    /// Where the error is, if it can be tied to the source.
+    pub span: Option<Span>,
+}
+
+impl CompileError {
+    fn at(span: Span, message: String) -> Self {
+        Self {
+            message,
+            span: Some(span),
+        }
+    }
+}
+
+impl From<&'static str> for CompileError {
+    fn from(message: &'static str) -> Self {
+        Self {
+            message: message.to_string(),
+            span: None,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{span}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
 /// Defines the `Expr` compiler.
 pub struct Compiler<'a, 'ctx> {
     pub context: &'ctx Context,
@@ -22,6 +65,8 @@ pub struct Compiler<'a, 'ctx> {
     pub fpm: &'a PassManager<FunctionValue<'ctx>>,
     pub module: &'a Module<'ctx>,
     pub function: &'a Function,
+    /// Path of the module `function` is declared in, empty at the top level.
+    pub scope: &'a [String],
 
     variables: HashMap<String, PointerValue<'ctx>>,
     fn_value_opt: Option<FunctionValue<'ctx>>,
@@ -34,21 +79,40 @@ impl<'a, 'ctx> Compiler<'a, 'ctx> {
         builder: &'a Builder<'ctx>,
         pass_manager: &'a PassManager<FunctionValue<'ctx>>,
         module: &'a Module<'ctx>,
+        scope: &'a [String],
         ast_node: &'a ASTNode,
-    ) -> Result<FunctionValue<'ctx>, &'static str> {
-        let function = match ast_node {
+        anonymous_functions: &mut usize,
+    ) -> Result<FunctionValue<'ctx>, CompileError> {
+        let mut function = match ast_node {
+            // the symbols externs name have no module, so can't be looked up
+            // under one
+            ASTNode::ExternNode(_) if !scope.is_empty() => {
+                return Err("Externs must be declared at the top level.".into())
+            }
             ASTNode::ExternNode(extern_node) => Function {
+                is_public: true,
                 prototype: extern_node.clone(),
                 body: None,
+                span: extern_node.span,
+                id: extern_node.id,
             },
             ASTNode::FunctionNode(function_node) => function_node.clone(),
+            ASTNode::ImportNode(_) => return Err("Unresolved import.".into()),
+            ASTNode::ModuleNode(_) => return Err("Modules are compiled item by item.".into()),
         };
+        // the parser leaves the functions wrapping top-level expressions
+        // unnamed, `anonymous_functions` counts the ones compiled so far
+        if function.prototype.name.is_empty() {
+            function.prototype.name = format!("{ANONYMOUS_FUNCTION_PREFIX}{anonymous_functions}");
+            *anonymous_functions += 1;
+        }
         let mut compiler = Compiler {
             context,
             builder,
             fpm: pass_manager,
             module,
             function: &function,
+            scope,
             fn_value_opt: None,
             variables: HashMap::new(),
         };
@@ -56,10 +120,57 @@ impl<'a, 'ctx> Compiler<'a, 'ctx> {
         compiler.compile_fn()
     }
 
-    /// Gets a defined function given its name.
-    #[inline]
-    fn get_function(&self, name: &str) -> Option<FunctionValue<'ctx>> {
-        self.module.get_function(name)
+    /// Gets a defined function given its path, for a call at `span`.
+    ///
+    /// Like a lexical scope, the path is looked up in the module of the
+    /// function being compiled first, then in each enclosing module in turn.
+    /// Functions not declared `pub` can only be called from within their module.
+    fn get_function(&self, path: &Path, span: Span) -> Result<FunctionValue<'ctx>, CompileError> {
+        for depth in (0..=self.scope.len()).rev() {
+            let modules: Vec<String> = self.scope[..depth]
+                .iter()
+                .chain(path.modules())
+                .cloned()
+                .collect();
+            if let Some(function) = self.module.get_function(&mangle(&modules, path.name())) {
+                let is_private = function.get_linkage() == Linkage::Internal;
+                if is_private && !self.scope.starts_with(&modules) {
+                    let caller: Vec<_> = self
+                        .scope
+                        .iter()
+                        .chain([&self.function.prototype.name])
+                        .map(String::as_str)
+                        .collect();
+                    return Err(CompileError::at(
+                        span,
+                        format!(
+                            "Cannot call the private function `{path}` from `{}`, outside its module.",
+                            caller.join("::")
+                        ),
+                    ));
+                }
+                return Ok(function);
+            }
+        }
+        Err(CompileError::at(
+            span,
+            format!("Unknown function `{path}`."),
+        ))
+    }
+
+    /// Linkage of the function being compiled.
+    ///
+    /// Functions not declared `pub` are internal to the LLVM module, except
+    /// for the top-level `main` and anonymous expressions the runtime calls.
+    fn linkage(&self) -> Option<Linkage> {
+        let function = self.function;
+        let name = &function.prototype.name;
+        let is_entry_point = self.scope.is_empty()
+            && (name == "main" || name.starts_with(ANONYMOUS_FUNCTION_PREFIX));
+        match function.body {
+            Some(_) if !function.is_public && !is_entry_point => Some(Linkage::Internal),
+            _ => None,
+        }
     }
 
     /// Returns the `FunctionValue` representing the function being compiled.
@@ -82,37 +193,65 @@ impl<'a, 'ctx> Compiler<'a, 'ctx> {
         builder.build_alloca(self.context.f64_type(), name)
     }
     /// Compiles the specified `Expr` into an LLVM `FloatValue`.
-    fn compile_expr(&mut self, expr: &Expression) -> Result<FloatValue<'ctx>, &'static str> {
-        match expr {
-            Expression::Literal(nb) => Ok(self.context.f64_type().const_float(*nb)),
+    fn compile_expr(&mut self, expr: &Expression) -> Result<FloatValue<'ctx>, CompileError> {
+        match &expr.kind {
+            ExpressionKind::Literal(nb) => Ok(self.context.f64_type().const_float(*nb)),
 
-            Expression::Variable(ref name) => match self.variables.get(name.as_str()) {
+            ExpressionKind::Variable(ref name) => match self.variables.get(name.as_str()) {
                 Some(var) => Ok(self
                     .builder
                     .build_load(*var, name.as_str())
                     .into_float_value()),
-                None => Err("Could not find a matching variable."),
+                None => Err(CompileError::at(
+                    expr.span,
+                    format!("Undefined variable `{name}`."),
+                )),
             },
 
-            Expression::Binary(op, ref left, ref right) => {
+            ExpressionKind::Unary(op, ref operand) => {
+                let operand = self.compile_expr(operand)?;
+
+                match op.as_str() {
+                    "-" => Ok(self.builder.build_float_neg(operand, "tmpneg")),
+                    "!" => {
+                        let zero_const = self.context.f64_type().const_float(0.0);
+                        Ok(self.compile_float_compare(FloatPredicate::OEQ, operand, zero_const))
+                    }
+                    // user-defined operators are compiled as calls to `unary<op>`
+                    _ => match self.module.get_function(&format!("unary{op}")) {
+                        Some(fun) => self.build_call(fun, &[operand]),
+                        None => Err(CompileError::at(
+                            expr.span,
+                            format!("Undefined unary operator `{op}`."),
+                        )),
+                    },
+                }
+            }
+
+            ExpressionKind::Binary(op, ref left, ref right) => {
                 if op == "=" {
                     // handle assignement
-                    let var_name = match **left {
-                        Expression::Variable(ref var_name) => var_name,
+                    let var_name = match left.kind {
+                        ExpressionKind::Variable(ref var_name) => var_name,
                         _ => {
-                            return Err("Expected variable as left-hand operator of assignement.");
+                            return Err(CompileError::at(
+                                left.span,
+                                "Expected variable as left-hand operator of assignement."
+                                    .to_string(),
+                            ));
                         }
                     };
 
                     let var_val = self.compile_expr(right)?;
-                    let var = self
-                        .variables
-                        .get(var_name.as_str())
-                        .ok_or("Undefined variable.")?;
+                    let var = self.variables.get(var_name.as_str()).ok_or_else(|| {
+                        CompileError::at(left.span, format!("Undefined variable `{var_name}`."))
+                    })?;
 
                     self.builder.build_store(*var, var_val);
 
                     Ok(var_val)
+                } else if op == "&&" || op == "||" {
+                    self.compile_logical(op, left, right)
                 } else {
                     let lhs = self.compile_expr(left)?;
                     let rhs = self.compile_expr(right)?;
@@ -122,114 +261,330 @@ impl<'a, 'ctx> Compiler<'a, 'ctx> {
                         "-" => Ok(self.builder.build_float_sub(lhs, rhs, "tmpsub")),
                         "*" => Ok(self.builder.build_float_mul(lhs, rhs, "tmpmul")),
                         "/" => Ok(self.builder.build_float_div(lhs, rhs, "tmpdiv")),
-                        "<" => Ok({
-                            let cmp = self.builder.build_float_compare(
-                                FloatPredicate::ULT,
-                                lhs,
-                                rhs,
-                                "tmpcmp",
-                            );
-
-                            self.builder.build_unsigned_int_to_float(
-                                cmp,
-                                self.context.f64_type(),
-                                "tmpbool",
-                            )
-                        }),
-                        ">" => Ok({
-                            let cmp = self.builder.build_float_compare(
-                                FloatPredicate::ULT,
-                                rhs,
-                                lhs,
-                                "tmpcmp",
-                            );
-
-                            self.builder.build_unsigned_int_to_float(
-                                cmp,
-                                self.context.f64_type(),
-                                "tmpbool",
-                            )
-                        }),
-                        _ => Err("Undefined binary operator."),
+                        "<" => Ok(self.compile_float_compare(FloatPredicate::ULT, lhs, rhs)),
+                        ">" => Ok(self.compile_float_compare(FloatPredicate::ULT, rhs, lhs)),
+                        "<=" => Ok(self.compile_float_compare(FloatPredicate::ULE, lhs, rhs)),
+                        ">=" => Ok(self.compile_float_compare(FloatPredicate::UGE, lhs, rhs)),
+                        "==" => Ok(self.compile_float_compare(FloatPredicate::OEQ, lhs, rhs)),
+                        "!=" => Ok(self.compile_float_compare(FloatPredicate::UNE, lhs, rhs)),
+                        // user-defined operators are compiled as calls to `binary<op>`
+                        _ => match self.module.get_function(&format!("binary{op}")) {
+                            Some(fun) => self.build_call(fun, &[lhs, rhs]),
+                            None => Err(CompileError::at(
+                                expr.span,
+                                format!("Undefined binary operator `{op}`."),
+                            )),
+                        },
                     }
                 }
             }
 
-            Expression::Call(ref fn_name, ref args) => match self.get_function(fn_name.as_str()) {
-                Some(fun) => {
-                    let mut compiled_args = Vec::with_capacity(args.len());
+            ExpressionKind::Call(ref fn_path, ref args) => {
+                let fun = self.get_function(fn_path, expr.span)?;
+                let mut compiled_args = Vec::with_capacity(args.len());
 
-                    for arg in args {
-                        compiled_args.push(self.compile_expr(arg)?);
-                    }
+                for arg in args {
+                    compiled_args.push(self.compile_expr(arg)?);
+                }
 
-                    let argsv: Vec<BasicMetadataValueEnum> = compiled_args
-                        .iter()
-                        .by_ref()
-                        .map(|&val| val.into())
-                        .collect();
+                self.build_call(fun, &compiled_args)
+            }
+            ExpressionKind::Block(exprs) => {
+                let mut block_val = self.context.f64_type().const_float(0.0);
 
-                    match self
-                        .builder
-                        .build_call(fun, argsv.as_slice(), "tmp")
-                        .try_as_basic_value()
-                        .left()
-                    {
-                        Some(value) => Ok(value.into_float_value()),
-                        None => Err("Invalid call produced."),
-                    }
+                for expr in exprs {
+                    block_val = self.compile_expr(expr)?;
                 }
-                None => Err("Unknown function."),
-            },
-            Expression::Conditional {
-                cond_expr,
-                if_block_expr,
-                else_block_expr,
+
+                Ok(block_val)
+            }
+
+            ExpressionKind::VarIn { variables, body } => {
+                let mut old_bindings = Vec::with_capacity(variables.len());
+
+                for (var_name, initializer) in variables {
+                    // compile the initializer before binding, so `var a = a in` reads the outer `a`
+                    let initial_val = match initializer {
+                        Some(init) => self.compile_expr(init)?,
+                        None => self.context.f64_type().const_float(0.0),
+                    };
+
+                    let alloca = self.create_entry_block_alloca(var_name);
+                    self.builder.build_store(alloca, initial_val);
+
+                    let old_binding = self.variables.insert(var_name.clone(), alloca);
+                    old_bindings.push((var_name, old_binding));
+                }
+
+                let body_val = self.compile_expr(body)?;
+
+                // restore the shadowed variables
+                for (var_name, old_binding) in old_bindings.into_iter().rev() {
+                    match old_binding {
+                        Some(alloca) => self.variables.insert(var_name.clone(), alloca),
+                        None => self.variables.remove(var_name.as_str()),
+                    };
+                }
+
+                Ok(body_val)
+            }
+
+            ExpressionKind::For {
+                var_name,
+                start,
+                cond,
+                step,
+                body,
             } => {
                 let parent = self.fn_value();
                 let zero_const = self.context.f64_type().const_float(0.0);
 
-                let condition_val = self.compile_expr(cond_expr)?;
-                let condition_cmp = self.builder.build_float_compare(
+                // the loop variable lives in its own alloca, so mem2reg can promote it
+                let start_val = self.compile_expr(start)?;
+                let alloca = self.create_entry_block_alloca(var_name);
+                self.builder.build_store(alloca, start_val);
+                let old_binding = self.variables.insert(var_name.clone(), alloca);
+
+                let cond_block = self.context.append_basic_block(parent, "loopcond");
+                let loop_block = self.context.append_basic_block(parent, "loop");
+                let after_block = self.context.append_basic_block(parent, "afterloop");
+
+                self.builder.build_unconditional_branch(cond_block);
+
+                // condition is checked before every iteration
+                self.builder.position_at_end(cond_block);
+                let cond_val = self.compile_expr(cond)?;
+                let cond_cmp = self.builder.build_float_compare(
                     FloatPredicate::ONE,
-                    condition_val,
+                    cond_val,
                     zero_const,
-                    "ifcond",
+                    "loopcond",
                 );
+                self.builder
+                    .build_conditional_branch(cond_cmp, loop_block, after_block);
+
+                // body, then step
+                self.builder.position_at_end(loop_block);
+                self.compile_expr(body)?;
+                let step_val = match step {
+                    Some(step) => self.compile_expr(step)?,
+                    None => self.context.f64_type().const_float(1.0),
+                };
+                let cur_val = self.builder.build_load(alloca, var_name).into_float_value();
+                let next_val = self.builder.build_float_add(cur_val, step_val, "nextvar");
+                self.builder.build_store(alloca, next_val);
+                self.builder.build_unconditional_branch(cond_block);
+
+                self.builder.position_at_end(after_block);
+
+                match old_binding {
+                    Some(old_alloca) => self.variables.insert(var_name.clone(), old_alloca),
+                    None => self.variables.remove(var_name.as_str()),
+                };
+
+                Ok(zero_const)
+            }
+
+            ExpressionKind::While { cond, body } => {
+                let parent = self.fn_value();
+                let zero_const = self.context.f64_type().const_float(0.0);
+
+                let cond_block = self.context.append_basic_block(parent, "loopcond");
+                let loop_block = self.context.append_basic_block(parent, "loop");
+                let after_block = self.context.append_basic_block(parent, "afterloop");
 
-                let if_block = self.context.append_basic_block(parent, "ifblock");
-                let else_block = self.context.append_basic_block(parent, "elseblock");
-                let rest_block = self.context.append_basic_block(parent, "rest");
+                self.builder.build_unconditional_branch(cond_block);
 
+                self.builder.position_at_end(cond_block);
+                let cond_val = self.compile_expr(cond)?;
+                let cond_cmp = self.builder.build_float_compare(
+                    FloatPredicate::ONE,
+                    cond_val,
+                    zero_const,
+                    "loopcond",
+                );
                 self.builder
-                    .build_conditional_branch(condition_cmp, if_block, else_block);
-
-                //if block
-                self.builder.position_at_end(if_block);
-                let if_block_val = self.compile_expr(if_block_expr)?;
-                self.builder.build_unconditional_branch(rest_block);
-                let if_basic_block = self.builder.get_insert_block().unwrap();
-
-                // else block
-                self.builder.position_at_end(else_block);
-                let else_val = self.compile_expr(else_block_expr)?;
-                self.builder.build_unconditional_branch(rest_block);
-                let else_basic_block = self.builder.get_insert_block().unwrap();
-
-                self.builder.position_at_end(rest_block);
-                let phi = self.builder.build_phi(self.context.f64_type(), "iftmp");
-                phi.add_incoming(&[
-                    (&if_block_val, if_basic_block),
-                    (&else_val, else_basic_block),
-                ]);
-
-                Ok(phi.as_basic_value().into_float_value())
+                    .build_conditional_branch(cond_cmp, loop_block, after_block);
+
+                self.builder.position_at_end(loop_block);
+                self.compile_expr(body)?;
+                self.builder.build_unconditional_branch(cond_block);
+
+                self.builder.position_at_end(after_block);
+
+                Ok(zero_const)
             }
+
+            ExpressionKind::Conditional {
+                cond_expr,
+                if_block_expr,
+                else_block_expr,
+            } => self.compile_conditional(cond_expr, if_block_expr, else_block_expr.as_deref()),
         }
     }
 
+    /// Compiles an `if`/`else if`/`else` chain.
+    ///
+    /// Every arm branches to a single merge block, so a chain of `n` arms needs
+    /// one phi instead of `n` nested ones. Without a final `else`, the last
+    /// condition jumps straight to the merge block and the chain evaluates to 0.
+    fn compile_conditional(
+        &mut self,
+        mut cond_expr: &Expression,
+        mut if_block_expr: &Expression,
+        mut else_block_expr: Option<&Expression>,
+    ) -> Result<FloatValue<'ctx>, CompileError> {
+        let parent = self.fn_value();
+        let zero_const = self.context.f64_type().const_float(0.0);
+        let rest_block = self.context.append_basic_block(parent, "rest");
+        let mut incoming = Vec::new();
+
+        loop {
+            let condition_val = self.compile_expr(cond_expr)?;
+            let condition_cmp = self.builder.build_float_compare(
+                FloatPredicate::ONE,
+                condition_val,
+                zero_const,
+                "ifcond",
+            );
+
+            let if_block = self.context.prepend_basic_block(rest_block, "ifblock");
+            let else_block = match else_block_expr {
+                Some(_) => self.context.prepend_basic_block(rest_block, "elseblock"),
+                None => {
+                    incoming.push((zero_const, self.builder.get_insert_block().unwrap()));
+                    rest_block
+                }
+            };
+
+            self.builder
+                .build_conditional_branch(condition_cmp, if_block, else_block);
+
+            // if block
+            self.builder.position_at_end(if_block);
+            let if_block_val = self.compile_expr(if_block_expr)?;
+            self.builder.build_unconditional_branch(rest_block);
+            incoming.push((if_block_val, self.builder.get_insert_block().unwrap()));
+
+            // else block, continuing the chain on `else if`
+            match else_block_expr {
+                None => break,
+                Some(Expression {
+                    kind:
+                        ExpressionKind::Conditional {
+                            cond_expr: next_cond_expr,
+                            if_block_expr: next_if_block_expr,
+                            else_block_expr: next_else_block_expr,
+                        },
+                    ..
+                }) => {
+                    self.builder.position_at_end(else_block);
+                    cond_expr = next_cond_expr;
+                    if_block_expr = next_if_block_expr;
+                    else_block_expr = next_else_block_expr.as_deref();
+                }
+                Some(else_expr) => {
+                    self.builder.position_at_end(else_block);
+                    let else_val = self.compile_expr(else_expr)?;
+                    self.builder.build_unconditional_branch(rest_block);
+                    incoming.push((else_val, self.builder.get_insert_block().unwrap()));
+                    break;
+                }
+            }
+        }
+
+        self.builder.position_at_end(rest_block);
+        let phi = self.builder.build_phi(self.context.f64_type(), "iftmp");
+        for (value, block) in &incoming {
+            phi.add_incoming(&[(value, *block)]);
+        }
+
+        Ok(phi.as_basic_value().into_float_value())
+    }
+
+    /// Builds a call to `fun` with the already compiled `args`.
+    fn build_call(
+        &self,
+        fun: FunctionValue<'ctx>,
+        args: &[FloatValue<'ctx>],
+    ) -> Result<FloatValue<'ctx>, CompileError> {
+        let argsv: Vec<BasicMetadataValueEnum> = args.iter().map(|&val| val.into()).collect();
+
+        match self
+            .builder
+            .build_call(fun, argsv.as_slice(), "tmp")
+            .try_as_basic_value()
+            .left()
+        {
+            Some(value) => Ok(value.into_float_value()),
+            None => Err("Invalid call produced.".into()),
+        }
+    }
+
+    /// Compares `lhs` and `rhs` using `predicate`, producing `1.0` or `0.0`.
+    fn compile_float_compare(
+        &self,
+        predicate: FloatPredicate,
+        lhs: FloatValue<'ctx>,
+        rhs: FloatValue<'ctx>,
+    ) -> FloatValue<'ctx> {
+        let cmp = self
+            .builder
+            .build_float_compare(predicate, lhs, rhs, "tmpcmp");
+
+        self.builder
+            .build_unsigned_int_to_float(cmp, self.context.f64_type(), "tmpbool")
+    }
+
+    /// Compiles a short-circuiting `&&` or `||`, producing `1.0` or `0.0`.
+    fn compile_logical(
+        &mut self,
+        op: &str,
+        left: &Expression,
+        right: &Expression,
+    ) -> Result<FloatValue<'ctx>, CompileError> {
+        let parent = self.fn_value();
+        let zero_const = self.context.f64_type().const_float(0.0);
+
+        let lhs = self.compile_expr(left)?;
+        let lhs_cmp =
+            self.builder
+                .build_float_compare(FloatPredicate::ONE, lhs, zero_const, "lhscond");
+        let lhs_basic_block = self.builder.get_insert_block().unwrap();
+
+        let rhs_block = self.context.append_basic_block(parent, "logicrhs");
+        let rest_block = self.context.append_basic_block(parent, "logicrest");
+
+        // only evaluate the right hand side if the left one does not decide the result
+        if op == "&&" {
+            self.builder
+                .build_conditional_branch(lhs_cmp, rhs_block, rest_block);
+        } else {
+            self.builder
+                .build_conditional_branch(lhs_cmp, rest_block, rhs_block);
+        }
+
+        self.builder.position_at_end(rhs_block);
+        let rhs = self.compile_expr(right)?;
+        let rhs_cmp =
+            self.builder
+                .build_float_compare(FloatPredicate::ONE, rhs, zero_const, "rhscond");
+        self.builder.build_unconditional_branch(rest_block);
+        let rhs_basic_block = self.builder.get_insert_block().unwrap();
+
+        self.builder.position_at_end(rest_block);
+        let phi = self.builder.build_phi(self.context.bool_type(), "logictmp");
+        phi.add_incoming(&[(&lhs_cmp, lhs_basic_block), (&rhs_cmp, rhs_basic_block)]);
+
+        Ok(self.builder.build_unsigned_int_to_float(
+            phi.as_basic_value().into_int_value(),
+            self.context.f64_type(),
+            "tmpbool",
+        ))
+    }
+
     /// Compiles the specified `Prototype` into an extern LLVM `FunctionValue`.
-    fn compile_prototype(&self, proto: &Prototype) -> Result<FunctionValue<'ctx>, &'static str> {
+    fn compile_prototype(&self, proto: &Prototype) -> Result<FunctionValue<'ctx>, CompileError> {
         let ret_type = self.context.f64_type();
         let args_types = std::iter::repeat(ret_type)
             .take(proto.args.len())
@@ -238,7 +593,15 @@ impl<'a, 'ctx> Compiler<'a, 'ctx> {
         let args_types = args_types.as_slice();
 
         let fn_type = self.context.f64_type().fn_type(args_types, false);
-        let fn_val = self.module.add_function(proto.name.as_str(), fn_type, None);
+        // externs name symbols defined elsewhere, and operators are global,
+        // so only the names of other functions depend on their module
+        let fn_name = match proto.kind {
+            PrototypeKind::Function if self.function.body.is_some() => {
+                mangle(self.scope, &proto.name)
+            }
+            _ => proto.name.clone(),
+        };
+        let fn_val = self.module.add_function(&fn_name, fn_type, self.linkage());
 
         // set arguments names
         for (i, arg) in fn_val.get_param_iter().enumerate() {
@@ -250,7 +613,7 @@ impl<'a, 'ctx> Compiler<'a, 'ctx> {
     }
 
     /// Compiles the specified `Function` into an LLVM `FunctionValue`.
-    fn compile_fn(&mut self) -> Result<FunctionValue<'ctx>, &'static str> {
+    fn compile_fn(&mut self) -> Result<FunctionValue<'ctx>, CompileError> {
         let proto = &self.function.prototype;
         let function = self.compile_prototype(proto)?;
 
@@ -293,7 +656,24 @@ impl<'a, 'ctx> Compiler<'a, 'ctx> {
                 function.delete();
             }
 
-            Err("Invalid generated function.")
+            Err("Invalid generated function.".into())
         }
     }
 }
+
+/// Name of the LLVM function for `name` declared in the module at `modules`.
+///
+/// Top-level names are kept as they are, so `main` and the functions called
+/// from outside keep their name. Other names are mangled like C++ nested
+/// names, `math::sq` becoming `_ZN4math2sqE`, so modules never collide.
+fn mangle(modules: &[String], name: &str) -> String {
+    if modules.is_empty() {
+        return name.to_string();
+    }
+    let mut mangled = "_ZN".to_string();
+    for segment in modules.iter().map(String::as_str).chain([name]) {
+        mangled.push_str(&format!("{}{segment}", segment.len()));
+    }
+    mangled.push('E');
+    mangled
+}
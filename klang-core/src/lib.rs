@@ -1,14 +1,22 @@
 mod context;
 mod ir_builder;
+mod loader;
 mod module;
 
-use anyhow::{Ok, Result};
+use anyhow::{anyhow, Ok, Result};
 use context::Compiler;
-use inkwell::{context::Context, memory_buffer::MemoryBuffer, passes::PassManager};
+use inkwell::{
+    builder::Builder, context::Context, memory_buffer::MemoryBuffer, module::Module,
+    passes::PassManager, values::FunctionValue,
+};
 use klang_ast::node::ASTNode;
-use klang_parse::{lexer::tokenize, parser::parse, token::Token};
+use klang_parse::{lexer::tokenize, parser::parse, token::SpannedToken};
 
-pub type ParseResult = Result<(Vec<ASTNode>, Vec<Token>)>;
+pub use context::{CompileError, ANONYMOUS_FUNCTION_PREFIX};
+pub use klang_ast::sexpr::SExpr;
+pub use loader::{load_program, Program};
+
+pub type ParseResult<'src> = Result<(Vec<ASTNode>, Vec<SpannedToken<'src>>)>;
 
 #[no_mangle]
 pub extern "C" fn printd(x: f64) -> f64 {
@@ -28,10 +36,21 @@ pub extern "C" fn putchard(x: f64) -> f64 {
 static EXTERNAL_FNS: [extern "C" fn(f64) -> f64; 2] = [putchard, printd];
 
 /// Parse the given_input_str and return the complete AST.
-pub fn parse_to_ast(input_str: &str) -> ParseResult {
+pub fn parse_to_ast(input_str: &str) -> ParseResult<'_> {
     let token_stream = tokenize(input_str)?;
     let parsed_nodes = vec![];
-    parse(&token_stream, &parsed_nodes).map_err(|e| anyhow::anyhow!("{e}"))
+    let (ast, left_tokens) = parse(&token_stream, &parsed_nodes)?;
+    Ok((ast, left_tokens.to_vec()))
+}
+
+/// Serializes `ast` to JSON, spans and ids included.
+pub fn ast_to_json(ast: &[ASTNode]) -> Result<String> {
+    Ok(serde_json::to_string_pretty(ast)?)
+}
+
+/// Reads an AST serialized by `ast_to_json`, or built by another frontend.
+pub fn ast_from_json(input_str: &str) -> Result<Vec<ASTNode>> {
+    Ok(serde_json::from_str(input_str)?)
 }
 
 pub type CodegenResult = Result<MemoryBuffer>;
@@ -53,11 +72,372 @@ pub fn ast_to_ir(context: &Context, ast: &[ASTNode]) -> CodegenResult {
 
     pass_manager.initialize();
 
-    for element in ast {
-        Compiler::compile(context, &builder, &pass_manager, &module, element)
-            .map_err(|e| anyhow::anyhow!("{e}"))?;
-    }
+    compile_items(
+        context,
+        &builder,
+        &pass_manager,
+        &module,
+        &mut Vec::new(),
+        ast,
+        &mut 0,
+    )?;
+
+    // private functions are internal to the module, drop the ones never called
+    let module_pass_manager = PassManager::<Module>::create(());
+    module_pass_manager.add_global_dce_pass();
+    module_pass_manager.run_on(&module);
 
     let memory_buffered_module = module.write_bitcode_to_memory();
     Ok(memory_buffered_module)
 }
+
+/// Convert the loaded `program` to llvm-ir, prefixing compile errors with the
+/// path of the file they are in.
+pub fn program_to_ir(context: &Context, program: &Program) -> CodegenResult {
+    ast_to_ir(context, &program.nodes).map_err(|error| {
+        let path = match error.downcast_ref::<CompileError>() {
+            Some(CompileError {
+                span: Some(span), ..
+            }) => program.path(span.file),
+            _ => None,
+        };
+        match path {
+            Some(path) => anyhow!("{}:{error}", path.display()),
+            None => error,
+        }
+    })
+}
+
+/// Compiles the `items` declared in the module at `scope`, and the items of
+/// the modules nested in it.
+///
+/// The functions wrapping top-level expressions are numbered in source order,
+/// `anonymous_functions` counting the ones compiled before `items`.
+fn compile_items<'ctx>(
+    context: &'ctx Context,
+    builder: &Builder<'ctx>,
+    pass_manager: &PassManager<FunctionValue<'ctx>>,
+    module: &Module<'ctx>,
+    scope: &mut Vec<String>,
+    items: &[ASTNode],
+    anonymous_functions: &mut usize,
+) -> Result<()> {
+    for item in items {
+        if let ASTNode::ModuleNode(inner) = item {
+            scope.push(inner.name.clone());
+            compile_items(
+                context,
+                builder,
+                pass_manager,
+                module,
+                scope,
+                &inner.items,
+                anonymous_functions,
+            )?;
+            scope.pop();
+            continue;
+        }
+        Compiler::compile(
+            context,
+            builder,
+            pass_manager,
+            module,
+            scope,
+            item,
+            anonymous_functions,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use inkwell::{
+        context::Context,
+        module::{Linkage, Module},
+        OptimizationLevel,
+    };
+    use klang_ast::{
+        id::NodeId,
+        node::{self, ASTNode},
+        span::{FileId, Span},
+    };
+    use klang_parse::{
+        lexer::{tokenize, tokenize_file},
+        parser::{parse, parse_with_settings, Associativity, ParserSettings},
+    };
+
+    use super::{ast_to_ir, parse_to_ast, program_to_ir, Program};
+
+    /// Compiles `input_str` into a module of `context`.
+    fn compile<'ctx>(context: &'ctx Context, input_str: &str) -> anyhow::Result<Module<'ctx>> {
+        let (ast, _) = parse_to_ast(input_str)?;
+        let buffer = ast_to_ir(context, &ast)?;
+        context
+            .create_module_from_ir(buffer)
+            .map_err(|e| anyhow::anyhow!("{e:?}"))
+    }
+
+    /// Compiles `definitions` with a `main` function returning `expr`, and
+    /// runs it.
+    fn evaluate(definitions: &str, expr: &str) -> f64 {
+        let context = Context::create();
+        let module = compile(&context, &format!("{definitions}\nfun main() {{ {expr} }}"))
+            .unwrap_or_else(|error| panic!("cannot compile `{expr}`: {error}"));
+        let engine = module
+            .create_jit_execution_engine(OptimizationLevel::None)
+            .unwrap();
+        unsafe {
+            let main = engine
+                .get_function::<unsafe extern "C" fn() -> f64>("main")
+                .unwrap();
+            main.call()
+        }
+    }
+
+    #[test]
+    fn compile_var_in() {
+        assert_eq!(
+            evaluate("", "var a = 1, b = a + 1, c in a * 100 + b * 10 + c"),
+            120.0
+        );
+        // the inner `a` is initialized from the outer one and shadows it
+        let shadow = "fun f(a) { (var a = a + 1 in a) * 10 + a }";
+        assert_eq!(evaluate(shadow, "f(1)"), 21.0);
+    }
+
+    #[test]
+    fn compile_loops() {
+        let sum = "fun sum(n, total) { for i = 1, i < n { total = total + i }; total }";
+        assert_eq!(evaluate(sum, "sum(5, 0)"), 10.0);
+        assert_eq!(evaluate(sum, "sum(1, 7)"), 7.0);
+        let step = "fun step(total) { for i = 0, i < 10, 3 { total = total + i }; total }";
+        assert_eq!(evaluate(step, "step(0)"), 18.0);
+        // loops evaluate to 0
+        assert_eq!(evaluate("", "for i = 0, i < 3 { 7 }"), 0.0);
+
+        let count = "fun count(n, steps) { while n < 10 { n = n + 3; steps = steps + 1 }; \
+                     steps * 100 + n }";
+        assert_eq!(evaluate(count, "count(0, 0)"), 412.0);
+        assert_eq!(evaluate(count, "count(20, 0)"), 20.0);
+        assert_eq!(evaluate("", "while 0 { 7 }"), 0.0);
+    }
+
+    #[test]
+    fn compile_conditionals() {
+        // without `else`, a false condition evaluates to 0
+        let positive = "fun positive(x) { if x > 0 { x } }";
+        assert_eq!(evaluate(positive, "positive(3)"), 3.0);
+        assert_eq!(evaluate(positive, "positive(-3)"), 0.0);
+
+        let grade = "fun grade(x) { if x < 10 { 1 } else if x < 20 { 2 } else if x < 30 { 3 } \
+                     else { 4 } }";
+        for (x, expected) in [(5, 1.0), (15, 2.0), (25, 3.0), (35, 4.0)] {
+            assert_eq!(
+                evaluate(grade, &format!("grade({x})")),
+                expected,
+                "grade({x})"
+            );
+        }
+        let partial = "fun partial(x) { if x == 1 { 10 } else if x == 2 { 20 } }";
+        for (x, expected) in [(1, 10.0), (2, 20.0), (3, 0.0)] {
+            assert_eq!(
+                evaluate(partial, &format!("partial({x})")),
+                expected,
+                "partial({x})"
+            );
+        }
+    }
+
+    #[test]
+    fn compile_operators() {
+        for (expr, expected) in [
+            ("1 < 2", 1.0),
+            ("2 < 2", 0.0),
+            ("2 > 1", 1.0),
+            ("2 > 2", 0.0),
+            ("2 <= 2", 1.0),
+            ("3 <= 2", 0.0),
+            ("2 >= 2", 1.0),
+            ("1 >= 2", 0.0),
+            ("2 == 2", 1.0),
+            ("1 == 2", 0.0),
+            ("1 != 2", 1.0),
+            ("2 != 2", 0.0),
+            ("1 && 2", 1.0),
+            ("1 && 0", 0.0),
+            ("0 && 1", 0.0),
+            ("0 || 3", 1.0),
+            ("0 || 0", 0.0),
+            ("-(1 + 2)", -3.0),
+            ("1 - -2", 3.0),
+            ("!0", 1.0),
+            ("!2", 0.0),
+            ("!(1 < 2) || 5 < 4", 0.0),
+        ] {
+            assert_eq!(evaluate("", expr), expected, "{expr}");
+        }
+    }
+
+    #[test]
+    fn compile_logical_operators_short_circuit() {
+        // the right operands would change `n` if they were evaluated
+        let touched = "fun touched(n) { 0 && (n = 1); 1 || (n = 2); n }";
+        assert_eq!(evaluate(touched, "touched(0)"), 0.0);
+        let touched = "fun touched(n) { 1 && (n = 1); 0 || (n = n + 2); n }";
+        assert_eq!(evaluate(touched, "touched(0)"), 3.0);
+    }
+
+    #[test]
+    fn compile_qualified_calls() {
+        let context = Context::create();
+        let module = compile(
+            &context,
+            "mod m { pub fun f(x) { x } mod n { pub fun g(x) { f(x) } } }
+             fun main() { m::f(1) + m::n::g(2) }",
+        )
+        .unwrap();
+        for name in ["main", "_ZN1m1fE", "_ZN1m1n1gE"] {
+            assert!(module.get_function(name).is_some(), "`{name}` is missing");
+        }
+
+        // module code reaches top-level externs like other enclosing items
+        let module = compile(
+            &context,
+            "use sin(x) mod m { pub fun f(x) { sin(x) } } fun main() { m::f(1) }",
+        )
+        .unwrap();
+        assert!(module.get_function("sin").is_some());
+
+        let error = compile(
+            &context,
+            "mod m { pub fun f(x) { x } } fun main() { m::g(1) }",
+        );
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "1:43: Unknown function `m::g`."
+        );
+    }
+
+    #[test]
+    fn compile_names_expressions_in_source_order() {
+        let context = Context::create();
+        let module = compile(
+            &context,
+            "1 + 1 fun main() { 0 } 2 mod m { pub fun f() { 1 } } m::f()",
+        )
+        .unwrap();
+        let names: Vec<_> = module
+            .get_functions()
+            .map(|function| function.get_name().to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "__anon_expr.0",
+                "main",
+                "__anon_expr.1",
+                "_ZN1m1fE",
+                "__anon_expr.2"
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_private_functions() {
+        let context = Context::create();
+        let module = compile(
+            &context,
+            "mod m {
+                fun helper(x) { x }
+                pub fun f(x) { helper(x) }
+                fun unused() { 1 }
+                mod n { pub fun g() { helper(2) } }
+            }
+            fun top() { 2 }
+            fun main() { m::f(1) + m::n::g() }",
+        )
+        .unwrap();
+        let linkage = |name| module.get_function(name).map(|f| f.get_linkage());
+        assert_eq!(linkage("_ZN1m6helperE"), Some(Linkage::Internal));
+        assert_eq!(linkage("_ZN1m1fE"), Some(Linkage::External));
+        assert_eq!(linkage("_ZN1m1n1gE"), Some(Linkage::External));
+        assert_eq!(linkage("main"), Some(Linkage::External));
+        // global DCE drops the private functions never called
+        assert_eq!(linkage("_ZN1m6unusedE"), None);
+        assert_eq!(linkage("top"), None);
+
+        let error = compile(
+            &context,
+            "mod m { fun helper(x) { x } }\nfun main() {\n  m::helper(1) }",
+        );
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "3:3: Cannot call the private function `m::helper` from `main`, outside its module."
+        );
+    }
+
+    #[test]
+    fn compile_errors_name_their_file() {
+        let token_stream = tokenize_file(FileId(1), "fun main() {\n  f(1) }").unwrap();
+        let (nodes, _) = parse(&token_stream, &[]).unwrap();
+        let program = Program {
+            nodes,
+            files: vec![PathBuf::from("lib.kl"), PathBuf::from("main.kl")],
+        };
+        let error = program_to_ir(&Context::create(), &program).unwrap_err();
+        assert_eq!(error.to_string(), "main.kl:2:3: Unknown function `f`.");
+
+        // programs loaded from JSON have no files to name
+        let program = Program {
+            files: Vec::new(),
+            ..program
+        };
+        let error = program_to_ir(&Context::create(), &program).unwrap_err();
+        assert_eq!(error.to_string(), "2:3: Unknown function `f`.");
+    }
+
+    #[test]
+    fn compile_errors_name_what_is_undefined() {
+        let context = Context::create();
+        let error = |input_str| compile(&context, input_str).unwrap_err().to_string();
+        assert_eq!(error("fun f() { x }"), "1:11: Undefined variable `x`.");
+        assert_eq!(
+            error("fun f() {\n  y = 1 }"),
+            "2:3: Undefined variable `y`."
+        );
+
+        // operators the parser was told about, but nothing defines
+        let mut settings = ParserSettings::default();
+        settings.add_binary_operator("%", 30, Associativity::Left);
+        settings.add_user_unary_operator("~");
+        for (input_str, expected) in [
+            ("fun f() { 2 % 1 }", "1:11: Undefined binary operator `%`."),
+            ("fun f() { 1 + ~2 }", "1:15: Undefined unary operator `~`."),
+        ] {
+            let token_stream = tokenize(input_str).unwrap();
+            let (ast, _) = parse_with_settings(&token_stream, &[], &mut settings.clone()).unwrap();
+            let error = ast_to_ir(&context, &ast).unwrap_err();
+            assert_eq!(error.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn compile_module_externs_fails() {
+        // the parser rejects them, other frontends may not
+        let (externs, _) = parse_to_ast("use f(x)").unwrap();
+        let ast = vec![ASTNode::ModuleNode(node::Module {
+            name: "m".to_string(),
+            items: externs,
+            span: Span::default(),
+            id: NodeId::default(),
+        })];
+        let error = ast_to_ir(&Context::create(), &ast).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Externs must be declared at the top level."
+        );
+    }
+}
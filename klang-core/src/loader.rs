@@ -0,0 +1,335 @@
+use std::{
+    collections::HashMap,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use klang_ast::{id::NodeId, node::ASTNode, span::FileId};
+use klang_parse::{
+    lexer::tokenize_file,
+    parser::{parse_to_import, ParserSettings},
+};
+
+use crate::ast_from_json;
+
+const JSON_EXTENSION: &str = "json";
+
+/// A program and the files it was loaded from.
+#[derive(PartialEq, Debug)]
+pub struct Program {
+    pub nodes: Vec<ASTNode>,
+    /// Canonical paths of the loaded files, indexed by the `FileId` of the
+    /// spans in `nodes`. Empty for programs loaded from JSON.
+    pub files: Vec<PathBuf>,
+}
+
+impl Program {
+    /// Path of the file with the id `file`, if it was loaded from one.
+    pub fn path(&self, file: FileId) -> Option<&Path> {
+        self.files.get(file.0 as usize).map(PathBuf::as_path)
+    }
+}
+
+/// Loads the program whose entry file is at `path`, together with every file
+/// it imports, directly or not.
+///
+/// Imports are resolved relative to the directory of the importing file. Each
+/// file is loaded once, however often it is imported, and its nodes come
+/// before the nodes of the files importing it, so the result compiles into a
+/// single module. Imports forming a cycle are an error.
+///
+/// Operators defined in a file can be used by the file itself, after their
+/// definition, and by the files importing it, after the import.
+///
+/// A `.json` entry file holds the AST of a whole program instead, as written
+/// by `ast_to_json`, so other frontends can compile through klang.
+pub fn load_program(path: &Path) -> Result<Program> {
+    if path.extension().is_some_and(|ext| ext == JSON_EXTENSION) {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("cannot read `{}`", path.display()))?;
+        let nodes =
+            ast_from_json(&json).with_context(|| format!("cannot load `{}`", path.display()))?;
+        return Ok(Program {
+            nodes,
+            files: Vec::new(),
+        });
+    }
+
+    let mut loader = Loader::default();
+    loader.load(path)?;
+    Ok(Program {
+        nodes: loader.nodes,
+        files: loader.files,
+    })
+}
+
+#[derive(Default)]
+struct Loader {
+    /// Canonical paths of the files seen so far, indexed by `FileId`.
+    files: Vec<PathBuf>,
+    /// Range of `nodes` holding the items of each loaded file.
+    loaded: HashMap<PathBuf, Range<usize>>,
+    /// Files waiting for their imports to load, the innermost one last.
+    loading: Vec<PathBuf>,
+    nodes: Vec<ASTNode>,
+    /// Id of the next node parsed, so ids stay unique across files.
+    next_node_id: NodeId,
+}
+
+impl Loader {
+    /// Loads the file at `path` and its imports, returning the range of
+    /// `nodes` holding the file's own items.
+    fn load(&mut self, path: &Path) -> Result<Range<usize>> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("cannot find `{}`", path.display()))?;
+        if let Some(start) = self.loading.iter().position(|file| *file == path) {
+            let cycle: Vec<_> = self.loading[start..]
+                .iter()
+                .chain([&path])
+                .map(|file| file.display().to_string())
+                .collect();
+            bail!("import cycle: {}", cycle.join(" -> "));
+        }
+        if let Some(items) = self.loaded.get(&path) {
+            return Ok(items.clone());
+        }
+
+        let file = FileId(self.files.len() as u32);
+        self.files.push(path.clone());
+        let source = fs::read_to_string(&path)
+            .with_context(|| format!("cannot read `{}`", path.display()))?;
+        let tokens = tokenize_file(file, &source)
+            .with_context(|| format!("cannot parse `{}`", path.display()))?;
+
+        // a file knows the operators it defines and those defined by the files
+        // it imports, each import loading before the items following it parse
+        let mut settings = ParserSettings::default();
+        let mut items = Vec::new();
+        let mut left_tokens = &tokens[..];
+        self.loading.push(path.clone());
+        while !left_tokens.is_empty() {
+            settings.next_node_id = self.next_node_id;
+            let (nodes, left) = parse_to_import(left_tokens, &mut settings)
+                .with_context(|| format!("cannot parse `{}`", path.display()))?;
+            self.next_node_id = settings.next_node_id;
+            left_tokens = left;
+
+            match nodes.last() {
+                Some(ASTNode::ImportNode(import)) => {
+                    let dir = path.parent().unwrap_or(Path::new(""));
+                    let imported = self
+                        .load(&dir.join(&import.path))
+                        .with_context(|| format!("imported from `{}`", path.display()))?;
+                    settings.register_operators(&self.nodes[imported]);
+                }
+                _ => {
+                    if let Some(token) = left_tokens.first() {
+                        bail!(
+                            "cannot parse `{}`: {}: item starting with {} is not complete",
+                            path.display(),
+                            token.span,
+                            token.token
+                        );
+                    }
+                }
+            }
+            items.extend(
+                nodes
+                    .into_iter()
+                    .filter(|node| !matches!(node, ASTNode::ImportNode(_))),
+            );
+        }
+        self.loading.pop();
+
+        let start = self.nodes.len();
+        self.nodes.extend(items);
+        self.loaded.insert(path, start..self.nodes.len());
+        Ok(start..self.nodes.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use klang_ast::{node::ASTNode, span::FileId};
+
+    use super::load_program;
+    use crate::ast_to_json;
+
+    /// Writes `files` to a new directory named after `test`, returning it.
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("klang-loader-{}-{test}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, source) in files {
+            fs::write(dir.join(name), source).unwrap();
+        }
+        dir
+    }
+
+    /// Names of the functions among `nodes`, in order.
+    fn function_names(nodes: &[ASTNode]) -> Vec<&str> {
+        nodes
+            .iter()
+            .filter_map(|node| match node {
+                ASTNode::FunctionNode(function) => Some(function.prototype.name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn load_diamond_imports_once() {
+        let dir = write_files(
+            "diamond",
+            &[
+                (
+                    "main.kl",
+                    "import \"a.kl\"\nimport \"./b.kl\"\nfun main() { a() + b() }",
+                ),
+                ("a.kl", "import \"c.kl\"\nfun a() { c() }"),
+                ("b.kl", "import \"./c.kl\"\nfun b() { c() }"),
+                ("c.kl", "fun c() { 1 }"),
+            ],
+        );
+        let nodes = load_program(&dir.join("main.kl")).unwrap().nodes;
+        assert_eq!(function_names(&nodes), vec!["c", "a", "b", "main"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_records_the_file_of_each_node() {
+        let dir = write_files(
+            "files",
+            &[
+                ("main.kl", "import \"lib.kl\"\nfun main() { f(1) }"),
+                ("lib.kl", "fun f(x) { x }"),
+            ],
+        );
+        let program = load_program(&dir.join("main.kl")).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let paths: Vec<_> = program
+            .nodes
+            .iter()
+            .map(|node| match node {
+                ASTNode::FunctionNode(function) => program.path(function.span.file).unwrap(),
+                node => panic!("unexpected node {node:?}"),
+            })
+            .collect();
+        assert_eq!(paths, vec![dir.join("lib.kl"), dir.join("main.kl")]);
+        assert_eq!(program.files.len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_import_cycle_fails() {
+        let dir = write_files(
+            "cycle",
+            &[
+                ("main.kl", "import \"a.kl\"\nfun main() { 1 }"),
+                ("a.kl", "import \"b.kl\"\nfun a() { 1 }"),
+                ("b.kl", "import \"a.kl\"\nfun b() { 1 }"),
+            ],
+        );
+        let error = load_program(&dir.join("main.kl")).unwrap_err();
+        let (a, b) = (
+            dir.canonicalize().unwrap().join("a.kl"),
+            dir.canonicalize().unwrap().join("b.kl"),
+        );
+        assert_eq!(
+            error.root_cause().to_string(),
+            format!(
+                "import cycle: {} -> {} -> {}",
+                a.display(),
+                b.display(),
+                a.display()
+            )
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_missing_import_fails() {
+        let dir = write_files(
+            "missing",
+            &[("main.kl", "import \"gone.kl\"\nfun main() { 1 }")],
+        );
+        let error = load_program(&dir.join("main.kl")).unwrap_err();
+        assert!(error.root_cause().to_string().contains("No such file"));
+        assert!(format!("{error:#}").starts_with("imported from `"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_scopes_operators_to_importing_files() {
+        let dir = write_files(
+            "operators",
+            &[
+                (
+                    "main.kl",
+                    "import \"op.kl\"\nimport \"other.kl\"\nfun main() { 1 | 2 }",
+                ),
+                ("op.kl", "fun binary| 5 (a, b) { a + b }"),
+                ("other.kl", "fun other() { 1 | 2 }"),
+            ],
+        );
+        let error = load_program(&dir.join("main.kl")).unwrap_err();
+        assert!(format!("{error:#}").contains("cannot parse `"));
+        assert!(format!("{error:#}").contains("other.kl"));
+
+        fs::write(
+            dir.join("other.kl"),
+            "import \"op.kl\"\nfun other() { 1 | 2 }",
+        )
+        .unwrap();
+        let nodes = load_program(&dir.join("main.kl")).unwrap().nodes;
+        assert_eq!(function_names(&nodes), vec!["binary|", "other", "main"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_ignores_imports_of_unparsed_items() {
+        let dir = write_files(
+            "unparsed",
+            &[("main.kl", "fun main( { import \"gone.kl\" }")],
+        );
+        let error = load_program(&dir.join("main.kl")).unwrap_err();
+        assert!(format!("{error:#}").starts_with("cannot parse `"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_json_programs() {
+        let dir = write_files(
+            "json",
+            &[
+                ("main.kl", "import \"lib.kl\"\nfun main() { f(1) }"),
+                ("lib.kl", "pub fun f(x) { x + 1 }"),
+                (
+                    "spanless.json",
+                    r#"[{"FunctionNode": {
+                        "is_public": false,
+                        "prototype": {"name": "main", "args": [], "kind": "Function"},
+                        "body": {"kind": {"Literal": 1.0}}
+                    }}]"#,
+                ),
+                ("broken.json", "[{"),
+            ],
+        );
+        let nodes = load_program(&dir.join("main.kl")).unwrap().nodes;
+        fs::write(dir.join("main.json"), ast_to_json(&nodes).unwrap()).unwrap();
+        assert_eq!(load_program(&dir.join("main.json")).unwrap().nodes, nodes);
+
+        let program = load_program(&dir.join("spanless.json")).unwrap();
+        assert_eq!(function_names(&program.nodes), vec!["main"]);
+        assert_eq!(program.path(FileId(0)), None);
+
+        let error = load_program(&dir.join("broken.json")).unwrap_err();
+        assert!(format!("{error:#}").starts_with("cannot load `"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+//! Times the lexer on a generated 3.5 MB source, next to the regex tokenizer
+//! it replaced.
+//!
+//! Run with `cargo bench -p klang-parse --bench tokenize`.
+
+use std::{
+    borrow::Cow,
+    time::{Duration, Instant},
+};
+
+use klang_ast::span::{FileId, Span};
+use klang_parse::{
+    lexer::tokenize,
+    token::{SpannedToken, Token},
+};
+use regex::{Captures, Regex};
+
+const SAMPLE: &str = r#"# running total of the squares below n
+fun binary| 5 (a, b) { if a { 1 } else if b { 1 } else { 0 } }
+fun sum_squares(n) {
+    var i = 0, total = 0 in
+    for i = 1, i <= n, 1 { total = total + i * i };
+    total
+}
+sum_squares(100) >= 338350 && !(1 != 1) | 0;
+"#;
+const INPUT_SIZE: usize = 3_500_000;
+const RUNS: u32 = 10;
+
+/// The regex tokenizer the lexer replaced, as it was before, except that it
+/// knows today's keywords. It blanks out comments, matches every token with
+/// a single regex and copies the text of identifiers and operators.
+fn regex_tokenize(input: &str) -> Vec<SpannedToken<'static>> {
+    let comment_re = Regex::new(r"(?m)#.*\n").unwrap();
+    let preprocessed = comment_re.replace_all(input, |caps: &Captures| {
+        format!("{}\n", " ".repeat(caps[0].len() - 1))
+    });
+    let token_re = Regex::new(concat!(
+        r"(?P<ident>\p{Alphabetic}\w*)|",
+        r"(?P<number>\d+\.?\d*)|",
+        r"(?P<delimiter>;)|",
+        r"(?P<oppar>\()|",
+        r"(?P<clpar>\))|",
+        r"(?P<opbrace>\{)|",
+        r"(?P<clbrace>\})|",
+        r"(?P<comma>,)|",
+        r"(?P<operator>\S)"
+    ))
+    .unwrap();
+
+    let mut result = Vec::new();
+    let (mut offset, mut line, mut column) = (0, 1, 1);
+    for cap in token_re.captures_iter(&preprocessed) {
+        let whole = cap.get(0).unwrap();
+        for ch in input[offset..whole.start()].chars() {
+            if ch == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        offset = whole.start();
+        let span = Span {
+            file: FileId::default(),
+            start: whole.start(),
+            end: whole.end(),
+            line,
+            column,
+        };
+
+        let token = if let Some(ident) = cap.name("ident") {
+            match ident.as_str() {
+                "fun" => Token::Fun,
+                "pub" => Token::Pub,
+                "use" => Token::Use,
+                "import" => Token::Import,
+                "mod" => Token::Mod,
+                "if" => Token::If,
+                "else" => Token::Else,
+                "var" => Token::Var,
+                "in" => Token::In,
+                "for" => Token::For,
+                "while" => Token::While,
+                name => Token::Ident(Cow::Owned(name.to_string())),
+            }
+        } else if let Some(number) = cap.name("number") {
+            Token::Number(number.as_str().parse().unwrap())
+        } else if cap.name("delimiter").is_some() {
+            Token::Delimiter
+        } else if cap.name("oppar").is_some() {
+            Token::OpeningParenthesis
+        } else if cap.name("clpar").is_some() {
+            Token::ClosingParenthesis
+        } else if cap.name("comma").is_some() {
+            Token::Comma
+        } else if cap.name("opbrace").is_some() {
+            Token::OpeningBrace
+        } else if cap.name("clbrace").is_some() {
+            Token::ClosingBrace
+        } else {
+            Token::Operator(Cow::Owned(cap["operator"].to_string()))
+        };
+        result.push(SpannedToken { token, span });
+    }
+    result
+}
+
+/// Average time of `RUNS` calls to `tokenize`, and how many tokens it made.
+fn time(tokenize: impl Fn() -> usize) -> (Duration, usize) {
+    let tokens = tokenize();
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        tokenize();
+    }
+    (start.elapsed() / RUNS, tokens)
+}
+
+fn main() {
+    let input = SAMPLE.repeat(INPUT_SIZE / SAMPLE.len());
+    println!("tokenizing {} bytes", input.len());
+
+    let (lexer, tokens) = time(|| tokenize(&input).unwrap().len());
+    println!("lexer: {tokens} tokens in {lexer:?}");
+    // the regex tokenizer splits two-character operators like `<=` in two
+    let (regex, tokens) = time(|| regex_tokenize(&input).len());
+    println!("regex tokenizer: {tokens} tokens in {regex:?}");
+    println!(
+        "the lexer is {:.1} times as fast",
+        regex.as_secs_f64() / lexer.as_secs_f64()
+    );
+}
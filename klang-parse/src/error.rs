@@ -0,0 +1,185 @@
+use std::fmt;
+
+use crate::{
+    lexer::LexError,
+    token::{SpannedToken, Token},
+};
+use klang_ast::{node::ASTNode, span::Span};
+
+/// Describes what the parser was looking for when it gave up.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Expected {
+    /// Exactly this token.
+    Token(Token<'static>),
+    /// Any identifier.
+    Identifier,
+    /// Any number literal.
+    Number,
+    /// Any string literal.
+    String,
+    /// The start of an expression.
+    Expression,
+    /// Any one of the listed alternatives.
+    OneOf(Vec<Expected>),
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Token(token) => write!(f, "{token}"),
+            Expected::Identifier => write!(f, "identifier"),
+            Expected::Number => write!(f, "number"),
+            Expected::String => write!(f, "string"),
+            Expected::Expression => write!(f, "expression"),
+            Expected::OneOf(alternatives) => {
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    match i {
+                        0 => write!(f, "{alternative}")?,
+                        _ if i + 1 == alternatives.len() => write!(f, " or {alternative}")?,
+                        _ => write!(f, ", {alternative}")?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// An error encountered while parsing a token stream.
+#[derive(PartialEq, Clone, Debug)]
+pub enum ParseError {
+    /// A token was found where something else was required.
+    UnexpectedToken {
+        expected: Expected,
+        found: Token<'static>,
+        span: Span,
+    },
+    /// A binary operator without a known precedence.
+    UnknownOperator { operator: String, span: Span },
+    /// A user-defined operator precedence outside of `1..=100`.
+    InvalidPrecedence { value: f64, span: Span },
+    /// A user-defined operator declared with the wrong number of arguments.
+    OperatorArity {
+        operator: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// A user-defined operator redefining a built-in one.
+    BuiltinOperator { operator: String, span: Span },
+    /// Expressions nested deeper than `ParserSettings::max_nesting_depth`.
+    NestingTooDeep { limit: usize, span: Span },
+    /// Source text that could not be turned into tokens.
+    Lex(LexError),
+    /// The input ended in the middle of an item, after the token at `span`.
+    UnexpectedEnd { span: Span },
+}
+
+impl ParseError {
+    pub(crate) fn unexpected(expected: Expected, found: &SpannedToken) -> Self {
+        ParseError::UnexpectedToken {
+            expected,
+            found: found.token.clone().into_owned(),
+            span: found.span,
+        }
+    }
+
+    /// Stable identifier of the error kind, safe for tools to match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseError::UnexpectedToken { .. } => "P0001",
+            ParseError::UnknownOperator { .. } => "P0002",
+            ParseError::InvalidPrecedence { .. } => "P0003",
+            ParseError::OperatorArity { .. } => "P0004",
+            ParseError::NestingTooDeep { .. } => "P0005",
+            ParseError::Lex(_) => "P0006",
+            ParseError::UnexpectedEnd { .. } => "P0007",
+            ParseError::BuiltinOperator { .. } => "P0008",
+        }
+    }
+
+    /// Location of the offending source text.
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::UnexpectedToken { span, .. }
+            | ParseError::UnknownOperator { span, .. }
+            | ParseError::InvalidPrecedence { span, .. }
+            | ParseError::OperatorArity { span, .. }
+            | ParseError::BuiltinOperator { span, .. }
+            | ParseError::NestingTooDeep { span, .. }
+            | ParseError::UnexpectedEnd { span } => *span,
+            ParseError::Lex(error) => error.span(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error[{}] at {}: ", self.code(), self.span())?;
+        match self {
+            ParseError::UnexpectedToken {
+                expected, found, ..
+            } => write!(f, "expected {expected}, found {found}"),
+            ParseError::UnknownOperator { operator, .. } => {
+                write!(f, "unknown operator `{operator}`")
+            }
+            ParseError::InvalidPrecedence { value, .. } => {
+                write!(
+                    f,
+                    "operator precedence must be a whole number from 1 to 100, found `{value}`"
+                )
+            }
+            ParseError::OperatorArity {
+                operator,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "operator `{operator}` must take {expected} argument(s), found {found}"
+            ),
+            ParseError::BuiltinOperator { operator, .. } => {
+                write!(f, "cannot redefine the built-in operator `{operator}`")
+            }
+            ParseError::NestingTooDeep { limit, .. } => {
+                write!(f, "expression nested more than {limit} levels deep")
+            }
+            ParseError::Lex(LexError::InvalidCharacter(ch, _)) => {
+                write!(f, "invalid character {ch:?}")
+            }
+            ParseError::Lex(LexError::InvalidNumber(number, _)) => {
+                write!(f, "invalid number literal `{number}`")
+            }
+            ParseError::Lex(LexError::UnterminatedString(_)) => {
+                write!(f, "unterminated string literal")
+            }
+            ParseError::UnexpectedEnd { .. } => write!(f, "unexpected end of input"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Every diagnostic of a parse that hit errors.
+///
+/// The parser recovers after each error, so `nodes` still holds the items
+/// that parsed successfully.
+#[derive(PartialEq, Clone, Debug)]
+pub struct ParseFailure {
+    pub errors: Vec<ParseError>,
+    pub nodes: Vec<ASTNode>,
+}
+
+impl fmt::Display for ParseFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseFailure {}
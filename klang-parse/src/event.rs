@@ -0,0 +1,149 @@
+use std::{mem, sync::Arc};
+
+use crate::{
+    syntax::{GreenElement, GreenNode, GreenToken, SyntaxKind},
+    token::SpannedToken,
+};
+
+/// A step of building a syntax tree, recorded while parsing.
+///
+/// Positions are token indices. Tokens aren't recorded, the tree builder adds
+/// every token between the positions of two events.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Event {
+    /// Opens a node in front of the token at `position`.
+    ///
+    /// `forward_parent` points to the start of a node that was only started
+    /// once this one was complete, but wraps it, like a binary expression
+    /// wrapping its left operand.
+    Start {
+        kind: SyntaxKind,
+        position: usize,
+        forward_parent: Option<usize>,
+    },
+    /// Closes the innermost open node in front of the token at `position`.
+    Finish { position: usize },
+    /// A `Start` already handled as the forward parent of an earlier one.
+    Tombstone,
+}
+
+/// Builds the green tree of `source` from its `tokens` and the parser's `events`.
+///
+/// The text between tokens becomes trivia tokens. Trivia in front of a node
+/// is put before the node, in its parent.
+pub(crate) fn build_tree(
+    source: &str,
+    tokens: &[SpannedToken],
+    mut events: Vec<Event>,
+) -> Arc<GreenNode> {
+    let mut builder = TreeBuilder {
+        source,
+        tokens,
+        emitted: 0,
+        text_position: 0,
+        stack: vec![(SyntaxKind::Root, Vec::new())],
+    };
+
+    for index in 0..events.len() {
+        match mem::replace(&mut events[index], Event::Tombstone) {
+            Event::Start {
+                kind,
+                position,
+                forward_parent,
+            } => {
+                let mut kinds = vec![kind];
+                let mut next = forward_parent;
+                while let Some(parent) = next {
+                    match mem::replace(&mut events[parent], Event::Tombstone) {
+                        Event::Start {
+                            kind,
+                            forward_parent,
+                            ..
+                        } => {
+                            kinds.push(kind);
+                            next = forward_parent;
+                        }
+                        _ => unreachable!("forward parent is not a node start"),
+                    }
+                }
+
+                builder.tokens_until(position);
+                builder.trivia_until(builder.token_start(position));
+                for kind in kinds.into_iter().rev() {
+                    builder.stack.push((kind, Vec::new()));
+                }
+            }
+            Event::Finish { position } => {
+                builder.tokens_until(position);
+                builder.finish_node();
+            }
+            Event::Tombstone => {}
+        }
+    }
+
+    builder.tokens_until(tokens.len());
+    builder.trivia_until(source.len());
+    let (kind, children) = builder.stack.pop().expect("root node");
+    debug_assert!(builder.stack.is_empty(), "unfinished syntax node");
+    Arc::new(GreenNode::new(kind, children))
+}
+
+struct TreeBuilder<'a, 'src> {
+    source: &'a str,
+    tokens: &'a [SpannedToken<'src>],
+    /// Number of tokens already added to the tree.
+    emitted: usize,
+    /// Offset of the first byte of `source` not in the tree yet.
+    text_position: usize,
+    /// Open nodes and their children so far.
+    stack: Vec<(SyntaxKind, Vec<GreenElement>)>,
+}
+
+impl TreeBuilder<'_, '_> {
+    /// Byte offset of the token at `position`, or the end of the source.
+    fn token_start(&self, position: usize) -> usize {
+        self.tokens
+            .get(position)
+            .map_or(self.source.len(), |token| token.span.start)
+    }
+
+    fn push_token(&mut self, kind: SyntaxKind, end: usize) {
+        let text = &self.source[self.text_position..end];
+        let token = GreenElement::Token(Arc::new(GreenToken::new(kind, text)));
+        self.stack.last_mut().expect("open node").1.push(token);
+        self.text_position = end;
+    }
+
+    /// Adds the tokens in front of `position`, with the trivia before them.
+    fn tokens_until(&mut self, position: usize) {
+        while self.emitted < position {
+            let token = &self.tokens[self.emitted];
+            self.trivia_until(token.span.start);
+            self.push_token(SyntaxKind::from(&token.token), token.span.end);
+            self.emitted += 1;
+        }
+    }
+
+    /// Splits the text up to `end` into whitespace, comments and unknown text.
+    fn trivia_until(&mut self, end: usize) {
+        while self.text_position < end {
+            let rest = &self.source[self.text_position..end];
+            let (kind, len) = if rest.starts_with('#') {
+                (SyntaxKind::Comment, rest.find('\n'))
+            } else if rest.starts_with(char::is_whitespace) {
+                let len = rest.find(|ch: char| !ch.is_whitespace());
+                (SyntaxKind::Whitespace, len)
+            } else {
+                let len = rest.find(|ch: char| ch.is_whitespace() || ch == '#');
+                (SyntaxKind::Unknown, len)
+            };
+            self.push_token(kind, self.text_position + len.unwrap_or(rest.len()));
+        }
+    }
+
+    fn finish_node(&mut self) {
+        let (kind, children) = self.stack.pop().expect("open node");
+        let node = GreenElement::Node(Arc::new(GreenNode::new(kind, children)));
+        self.stack.last_mut().expect("parent node").1.push(node);
+    }
+}
@@ -1,202 +1,474 @@
 use crate::{
-    expect_token,
-    parse::{error, Parse, PartParsingResult},
-    parse_try,
-    parser::ParserSettings,
-    token::Token,
+    error::{Expected, ParseError},
+    parse::{Marker, Parse, ParseStop, Parser, PartParsingResult},
+    parser::{BinaryOperator, ParserSettings},
+    syntax::SyntaxKind,
+    token::{SpannedToken, Token},
 };
 use klang_ast::{
-    expr::Expression,
-    function::{Function, Prototype},
+    expr::{Expression, ExpressionKind},
+    function::{Function, Prototype, PrototypeKind},
     node::ASTNode,
+    path::Path,
 };
 
+/// Wraps a top-level expression in a function without a name. Code
+/// generation names it, so it can be run like a script.
 impl Parse<ASTNode> for Expression {
-    fn parse(tokens: &mut Vec<Token>) -> PartParsingResult<ASTNode> {
-        let mut parsed_tokens = Vec::new();
-        let expression_partial = Expression::parse(tokens);
-        let expression = parse_try!(expression_partial, tokens, parsed_tokens);
+    fn parse(parser: &mut Parser, settings: &ParserSettings) -> PartParsingResult<ASTNode> {
+        let expression: Expression = Expression::parse(parser, settings)?;
+        let span = expression.span;
         let prototype = Prototype {
             name: "".to_string(),
             args: vec![],
+            kind: PrototypeKind::Function,
+            span,
+            id: parser.new_id(),
         };
         let lambda = Function {
+            is_public: false,
             prototype,
             body: Some(expression),
+            span,
+            id: parser.new_id(),
         };
 
-        PartParsingResult::Good(ASTNode::FunctionNode(lambda), parsed_tokens)
+        Ok(ASTNode::FunctionNode(lambda))
     }
 }
 
 impl Parse<Expression> for Expression {
-    fn parse(tokens: &mut Vec<Token>) -> PartParsingResult<Expression> {
-        let mut parsed_tokens = Vec::new();
-        let lhs_partial_parse = parse_primary_expr(tokens);
-        let lhs = parse_try!(lhs_partial_parse, tokens, parsed_tokens);
+    fn parse(parser: &mut Parser, settings: &ParserSettings) -> PartParsingResult<Expression> {
+        parse_expr(parser, settings, 0)
+    }
+}
+
+/// Closes the syntax node of `marker` and builds the expression parsed from it.
+fn finish_expr(
+    parser: &mut Parser,
+    marker: Marker,
+    syntax_kind: SyntaxKind,
+    kind: ExpressionKind,
+) -> Expression {
+    let (span, id) = parser.finish_node(marker, syntax_kind);
+    Expression { kind, span, id }
+}
+
+/// An operator waiting for its right operand.
+enum PendingOperator {
+    /// A prefix operator, its precedence if it has one, and its syntax node.
+    Unary(String, Option<i32>, Marker),
+    Binary(String, BinaryOperator),
+}
+
+/// A parsed operand and its syntax node.
+struct Operand {
+    expr: Expression,
+    marker: Marker,
+}
+
+impl PendingOperator {
+    /// Whether the pending operator takes the operand before `next` as its
+    /// right operand, instead of `next` taking it as its left one.
+    fn binds_before(&self, next: &BinaryOperator) -> bool {
+        match self {
+            PendingOperator::Unary(_, Some(precedence), _) => *precedence >= next.precedence,
+            PendingOperator::Unary(_, None, _) => true,
+            PendingOperator::Binary(_, pending) => pending.binds_before(next),
+        }
+    }
+
+    /// Replaces the operand(s) on top of `operands` with this operator applied to them.
+    fn apply(self, parser: &mut Parser, operands: &mut Vec<Operand>) {
+        let rhs = operands.pop().expect("operator without operand");
+        let operand = match self {
+            PendingOperator::Unary(op, _, marker) => {
+                let kind = ExpressionKind::Unary(op, Box::new(rhs.expr));
+                Operand {
+                    expr: finish_expr(parser, marker, SyntaxKind::UnaryExpr, kind),
+                    marker,
+                }
+            }
+            PendingOperator::Binary(op, _) => {
+                let lhs = operands
+                    .pop()
+                    .expect("binary operator without left operand");
+                let marker = parser.precede(lhs.marker);
+                let kind = ExpressionKind::Binary(op, Box::new(lhs.expr), Box::new(rhs.expr));
+                Operand {
+                    expr: finish_expr(parser, marker, SyntaxKind::BinaryExpr, kind),
+                    marker,
+                }
+            }
+        };
+        operands.push(operand);
+    }
+}
+
+/// Returns the next token's binary operator, if the next token is an operator.
+fn peek_binary_operator(
+    parser: &Parser,
+    settings: &ParserSettings,
+) -> Option<Result<(String, BinaryOperator), ParseError>> {
+    match parser.peek() {
+        Some(SpannedToken {
+            token: Token::Operator(op),
+            span,
+        }) => Some(match settings.binary_operator(op) {
+            Some(operator) => Ok((op.to_string(), *operator)),
+            None => Err(ParseError::UnknownOperator {
+                operator: op.to_string(),
+                span: *span,
+            }),
+        }),
+        _ => None,
+    }
+}
 
-        let starting_precedence = 0;
-        let expr_partial_parse = parse_binary_expr(tokens, starting_precedence, &lhs);
-        let expr = parse_try!(expr_partial_parse, tokens, parsed_tokens);
-        PartParsingResult::Good(expr, parsed_tokens)
+/// Fails once `depth` exceeds the nesting limit, blaming the next token.
+fn check_nesting(
+    parser: &Parser,
+    settings: &ParserSettings,
+    depth: usize,
+) -> Result<(), ParseError> {
+    match parser.peek() {
+        Some(next) if depth > settings.max_nesting_depth => Err(ParseError::NestingTooDeep {
+            limit: settings.max_nesting_depth,
+            span: next.span,
+        }),
+        _ => Ok(()),
     }
 }
 
-fn parse_binary_expr(
-    tokens: &mut Vec<Token>,
-    expr_precedence: i32,
-    lhs: &Expression,
+/// Parses an expression made of operators and primary expressions.
+///
+/// Operators wait on an explicit stack until their operands are complete, so
+/// chains of operators don't recurse. Only nested primary expressions do, and
+/// `depth`, counting the enclosing expressions and pending operators, is
+/// bounded by `settings.max_nesting_depth`.
+pub(crate) fn parse_expr(
+    parser: &mut Parser,
+    settings: &ParserSettings,
+    depth: usize,
 ) -> PartParsingResult<Expression> {
-    let mut result = lhs.clone();
-    let parser_settings = ParserSettings::default();
-    let mut parsed_tokens = Vec::new();
-    while let Some(Token::Operator(op)) = tokens.last().cloned() {
-        let (operator, precedence) = match parser_settings.operator_precedence.get(&op) {
-            Some(precedence) if *precedence >= expr_precedence => (op, precedence),
-            None => return error("unknown operator found"),
-            _ => break,
+    check_nesting(parser, settings, depth)?;
+
+    let mut operands = Vec::new();
+    let mut operators: Vec<PendingOperator> = Vec::new();
+
+    loop {
+        // prefix operators
+        while let Some(
+            next @ SpannedToken {
+                token: Token::Operator(op),
+                ..
+            },
+        ) = parser.peek()
+        {
+            if !settings.is_unary_operator(op) {
+                return Err(ParseError::unexpected(Expected::Expression, next).into());
+            }
+            check_nesting(parser, settings, depth + operators.len() + 1)?;
+            let marker = parser.start();
+            operators.push(PendingOperator::Unary(
+                op.to_string(),
+                settings.unary_operator(op),
+                marker,
+            ));
+            parser.bump();
+        }
+
+        let marker = parser.next_marker();
+        let expr = parse_primary_expr(parser, settings, depth + operators.len())?;
+        operands.push(Operand { expr, marker });
+
+        let (op, operator) = match peek_binary_operator(parser, settings) {
+            Some(peeked) => peeked?,
+            None => break,
         };
-        tokens.pop();
-        parsed_tokens.push(Token::Operator(operator.clone()));
-
-        // parse primary RHS expression
-        let rhs_partial_parse = parse_primary_expr(tokens);
-        let mut rhs = parse_try!(rhs_partial_parse, tokens, parsed_tokens);
-        // parse all the RHS operators until their precedence is
-        // bigger than the current one
-        while let Some(Token::Operator(op)) = tokens.last().cloned() {
-            let binary_rhs = match parser_settings.operator_precedence.get(&op) {
-                Some(pr) if pr > precedence => {
-                    let binary_expr_partial_parse =
-                        parse_binary_expr(tokens, expr_precedence, &rhs);
-                    parse_try!(binary_expr_partial_parse, tokens, parsed_tokens)
-                }
-                None => return error("unknown operator found"),
-                _ => break,
-            };
-            rhs = binary_rhs;
+        while operators
+            .last()
+            .is_some_and(|pending| pending.binds_before(&operator))
+        {
+            operators.pop().unwrap().apply(parser, &mut operands);
         }
-        result = Expression::Binary(operator.to_string(), Box::new(result), Box::new(rhs));
+        check_nesting(parser, settings, depth + operators.len() + 1)?;
+        operators.push(PendingOperator::Binary(op, operator));
+        parser.bump();
     }
 
-    PartParsingResult::Good(result, parsed_tokens)
+    while let Some(pending) = operators.pop() {
+        pending.apply(parser, &mut operands);
+    }
+    Ok(operands.pop().expect("expression without operand").expr)
 }
 
-fn parse_primary_expr(tokens: &mut Vec<Token>) -> PartParsingResult<Expression> {
-    match tokens.last() {
-        Some(&Token::Ident(_)) => parse_ident_expr(tokens),
-        Some(&Token::Number(_)) => parse_literal_expr(tokens),
-        Some(&Token::OpeningParenthesis) => parse_parenthesis_expr(tokens),
-        Some(&Token::If) => parse_if_else_expr(tokens),
-        None => PartParsingResult::NotComplete,
-        _ => error("unknown token when expecting an expression"),
+fn parse_primary_expr(
+    parser: &mut Parser,
+    settings: &ParserSettings,
+    depth: usize,
+) -> PartParsingResult<Expression> {
+    let Some(next) = parser.peek() else {
+        return Err(ParseStop::NotComplete);
+    };
+    match next.token {
+        Token::Ident(_) => parse_ident_expr(parser, settings, depth),
+        Token::Number(_) => parse_literal_expr(parser),
+        Token::OpeningParenthesis => parse_parenthesis_expr(parser, settings, depth),
+        Token::If => parse_if_else_expr(parser, settings, depth),
+        Token::Var => parse_var_expr(parser, settings, depth),
+        Token::For => parse_for_expr(parser, settings, depth),
+        Token::While => parse_while_expr(parser, settings, depth),
+        _ => Err(ParseError::unexpected(Expected::Expression, next).into()),
     }
 }
 
-fn parse_if_else_expr(tokens: &mut Vec<Token>) -> PartParsingResult<Expression> {
-    // consume `if`
-    tokens.pop();
-    let mut parsed_tokens = vec![Token::If];
-    let cond_partial_parsed = Expression::parse(tokens);
-    let condition = parse_try!(cond_partial_parsed, tokens, parsed_tokens);
-
-    expect_token!(
-        [Token::OpeningBrace, Token::OpeningBrace, ()] <= tokens,
-        parsed_tokens,
-        "expected `{` after if's condition"
-    );
-    let if_block_partial_parsed = Expression::parse(tokens);
-    let if_block_expr = parse_try!(if_block_partial_parsed, tokens, parsed_tokens);
-
-    expect_token!(
-        [Token::ClosingBrace, Token::ClosingBrace, ()] <= tokens,
-        parsed_tokens,
-        "expected `}` after if's body"
-    );
-
-    expect_token!(
-        [Token::Else, Token::Else, ()] <= tokens,
-        parsed_tokens,
-        "expected else after if's body"
-    );
-
-    expect_token!(
-        [Token::OpeningBrace, Token::OpeningBrace, ()] <= tokens,
-        parsed_tokens,
-        "expected `{` after else"
-    );
-
-    let else_block_partial_parsed = Expression::parse(tokens);
-    let else_block_expr = parse_try!(else_block_partial_parsed, tokens, parsed_tokens);
-
-    expect_token!(
-        [Token::ClosingBrace, Token::ClosingBrace, ()] <= tokens,
-        parsed_tokens,
-        "expected `}` after else's body"
-    );
-
-    PartParsingResult::Good(
-        Expression::Conditional {
-            cond_expr: Box::new(condition),
-            if_block_expr: Box::new(if_block_expr),
-            else_block_expr: Box::new(else_block_expr),
-        },
-        parsed_tokens,
-    )
-}
-
-fn parse_ident_expr(tokens: &mut Vec<Token>) -> PartParsingResult<Expression> {
-    let mut parsed_tokens = Vec::new();
-    let name = expect_token!(
-        [Token::Ident(name), Token::Ident(name.clone()), name] <= tokens,
-        parsed_tokens,
-        "identificator expected"
-    );
-
-    expect_token!(
-        [Token::OpeningParenthesis, Token::OpeningParenthesis, ()]
-        else {return PartParsingResult::Good(Expression::Variable(name), parsed_tokens)}
-        <= tokens, parsed_tokens);
+/// Parses `for i = start, cond, step { body }`, where `step` defaults to 1.
+fn parse_for_expr(
+    parser: &mut Parser,
+    settings: &ParserSettings,
+    depth: usize,
+) -> PartParsingResult<Expression> {
+    let marker = parser.start();
+    // consume `for`
+    parser.bump();
+    let var_name = parser.expect_ident()?.to_string();
+    parser.expect(Token::Operator("=".into()))?;
 
-    let mut args = Vec::new();
+    let start = parse_expr(parser, settings, depth + 1)?;
+    parser.expect(Token::Comma)?;
+    let cond = parse_expr(parser, settings, depth + 1)?;
+
+    let mut step = None;
+    if parser.eat(&Token::Comma) {
+        step = Some(Box::new(parse_expr(parser, settings, depth + 1)?));
+    }
+
+    let body = parse_block_expr(parser, settings, depth + 1)?;
+
+    let kind = ExpressionKind::For {
+        var_name,
+        start: Box::new(start),
+        cond: Box::new(cond),
+        step,
+        body: Box::new(body),
+    };
+    Ok(finish_expr(parser, marker, SyntaxKind::ForExpr, kind))
+}
+
+/// Parses `while cond { body }`.
+fn parse_while_expr(
+    parser: &mut Parser,
+    settings: &ParserSettings,
+    depth: usize,
+) -> PartParsingResult<Expression> {
+    let marker = parser.start();
+    // consume `while`
+    parser.bump();
+    let cond = parse_expr(parser, settings, depth + 1)?;
+    let body = parse_block_expr(parser, settings, depth + 1)?;
+
+    let kind = ExpressionKind::While {
+        cond: Box::new(cond),
+        body: Box::new(body),
+    };
+    Ok(finish_expr(parser, marker, SyntaxKind::WhileExpr, kind))
+}
+
+/// Parses `{ a; b; c }`, whose value is the value of its last expression.
+///
+/// A block holding a single expression is returned as that expression.
+pub(crate) fn parse_block_expr(
+    parser: &mut Parser,
+    settings: &ParserSettings,
+    depth: usize,
+) -> PartParsingResult<Expression> {
+    let marker = parser.start();
+    parser.expect(Token::OpeningBrace)?;
+
+    let mut exprs = Vec::new();
+    while !parser.eat(&Token::ClosingBrace) {
+        exprs.push(parse_expr(parser, settings, depth + 1)?);
+
+        match parser.next_token()? {
+            SpannedToken {
+                token: Token::Delimiter,
+                ..
+            } => continue,
+            SpannedToken {
+                token: Token::ClosingBrace,
+                ..
+            } => break,
+            found => {
+                let expected = Expected::OneOf(vec![
+                    Expected::Token(Token::Delimiter),
+                    Expected::Token(Token::ClosingBrace),
+                ]);
+                return Err(ParseError::unexpected(expected, found).into());
+            }
+        }
+    }
+
+    if exprs.len() == 1 {
+        parser.finish(marker, SyntaxKind::BlockExpr);
+        return Ok(exprs.pop().unwrap());
+    }
+    let kind = ExpressionKind::Block(exprs);
+    Ok(finish_expr(parser, marker, SyntaxKind::BlockExpr, kind))
+}
+
+/// Parses `var a = 1, b in body`, where variables without an initializer start at 0.
+fn parse_var_expr(
+    parser: &mut Parser,
+    settings: &ParserSettings,
+    depth: usize,
+) -> PartParsingResult<Expression> {
+    let marker = parser.start();
+    // consume `var`
+    parser.bump();
+    let mut variables = Vec::new();
     loop {
-        expect_token!(
-            [Token::ClosingParenthesis, Token::ClosingParenthesis, break;
-             Token::Comma, Token::Comma, continue]
-            else {
-                let expr_partial_parsing: PartParsingResult<Expression> = Expression::parse(tokens);
-                let expr = parse_try!(expr_partial_parsing, tokens, parsed_tokens);
-                args.push(expr);
+        let binding_marker = parser.start();
+        let name = parser.expect_ident()?.to_string();
+
+        let mut initializer = None;
+        if parser.eat(&Token::Operator("=".into())) {
+            initializer = Some(parse_expr(parser, settings, depth + 1)?);
+        }
+        parser.finish(binding_marker, SyntaxKind::VarBinding);
+        variables.push((name, initializer));
+
+        match parser.next_token()? {
+            SpannedToken {
+                token: Token::Comma,
+                ..
+            } => continue,
+            SpannedToken {
+                token: Token::In, ..
+            } => break,
+            found => {
+                let expected = Expected::OneOf(vec![
+                    Expected::Token(Token::Comma),
+                    Expected::Token(Token::In),
+                ]);
+                return Err(ParseError::unexpected(expected, found).into());
             }
-            <= tokens, parsed_tokens);
+        }
     }
-    PartParsingResult::Good(Expression::Call(name, args), parsed_tokens)
+
+    let body = parse_expr(parser, settings, depth + 1)?;
+
+    let kind = ExpressionKind::VarIn {
+        variables,
+        body: Box::new(body),
+    };
+    Ok(finish_expr(parser, marker, SyntaxKind::VarExpr, kind))
 }
 
-fn parse_literal_expr(tokens: &mut Vec<Token>) -> PartParsingResult<Expression> {
-    let mut parsed_tokens = Vec::new();
+fn parse_if_else_expr(
+    parser: &mut Parser,
+    settings: &ParserSettings,
+    depth: usize,
+) -> PartParsingResult<Expression> {
+    let marker = parser.start();
+    // consume `if`
+    parser.bump();
+    let condition = parse_expr(parser, settings, depth + 1)?;
+    let if_block_expr = parse_block_expr(parser, settings, depth + 1)?;
 
-    let value = expect_token!(
-        [Token::Number(val), Token::Number(val), val] <= tokens,
-        parsed_tokens,
-        "literal expected"
-    );
+    let mut else_block_expr = None;
+    if parser.eat(&Token::Else) {
+        let else_expr = match parser.peek() {
+            Some(SpannedToken {
+                token: Token::If, ..
+            }) => parse_if_else_expr(parser, settings, depth + 1)?,
+            Some(SpannedToken {
+                token: Token::OpeningBrace,
+                ..
+            })
+            | None => parse_block_expr(parser, settings, depth + 1)?,
+            Some(found) => {
+                let expected = Expected::OneOf(vec![
+                    Expected::Token(Token::If),
+                    Expected::Token(Token::OpeningBrace),
+                ]);
+                return Err(ParseError::unexpected(expected, found).into());
+            }
+        };
+        else_block_expr = Some(Box::new(else_expr));
+    }
 
-    PartParsingResult::Good(Expression::Literal(value), parsed_tokens)
+    let kind = ExpressionKind::Conditional {
+        cond_expr: Box::new(condition),
+        if_block_expr: Box::new(if_block_expr),
+        else_block_expr,
+    };
+    Ok(finish_expr(parser, marker, SyntaxKind::IfExpr, kind))
 }
 
-fn parse_parenthesis_expr(tokens: &mut Vec<Token>) -> PartParsingResult<Expression> {
+fn parse_ident_expr(
+    parser: &mut Parser,
+    settings: &ParserSettings,
+    depth: usize,
+) -> PartParsingResult<Expression> {
+    let marker = parser.start();
+    let mut segments = vec![parser.expect_ident()?.to_string()];
+    while parser.eat(&Token::PathSeparator) {
+        segments.push(parser.expect_ident()?.to_string());
+    }
+
+    if segments.len() == 1 && parser.peek_token() != Some(&Token::OpeningParenthesis) {
+        let kind = ExpressionKind::Variable(segments.remove(0));
+        return Ok(finish_expr(parser, marker, SyntaxKind::VariableExpr, kind));
+    }
+    let arguments_marker = parser.start();
+    // modules only hold functions, so a qualified name has to be called
+    parser.expect(Token::OpeningParenthesis)?;
+
+    let mut args = Vec::new();
+    loop {
+        match parser.peek_token() {
+            Some(Token::ClosingParenthesis) => {
+                parser.bump();
+                break;
+            }
+            Some(Token::Comma) => {
+                parser.bump();
+            }
+            _ => args.push(parse_expr(parser, settings, depth + 1)?),
+        }
+    }
+    parser.finish(arguments_marker, SyntaxKind::ArgumentList);
+
+    let kind = ExpressionKind::Call(Path { segments }, args);
+    Ok(finish_expr(parser, marker, SyntaxKind::CallExpr, kind))
+}
+
+fn parse_literal_expr(parser: &mut Parser) -> PartParsingResult<Expression> {
+    let marker = parser.start();
+    match parser.next_token()? {
+        SpannedToken {
+            token: Token::Number(value),
+            ..
+        } => {
+            let kind = ExpressionKind::Literal(*value);
+            Ok(finish_expr(parser, marker, SyntaxKind::LiteralExpr, kind))
+        }
+        found => Err(ParseError::unexpected(Expected::Number, found).into()),
+    }
+}
+
+fn parse_parenthesis_expr(
+    parser: &mut Parser,
+    settings: &ParserSettings,
+    depth: usize,
+) -> PartParsingResult<Expression> {
+    let marker = parser.start();
     // Consume `(`.
-    tokens.pop();
-    let mut parsed_tokens = vec![Token::OpeningParenthesis];
-    let expr: PartParsingResult<Expression> = Expression::parse(tokens);
-    let expr = parse_try!(expr, tokens, parsed_tokens);
-
-    expect_token!(
-        [Token::ClosingParenthesis, Token::ClosingParenthesis, ()] <= tokens,
-        parsed_tokens,
-        "')' expected"
-    );
-
-    PartParsingResult::Good(expr, parsed_tokens)
+    parser.bump();
+    let expr = parse_expr(parser, settings, depth + 1)?;
+    parser.expect(Token::ClosingParenthesis)?;
+    parser.finish(marker, SyntaxKind::ParenExpr);
+
+    Ok(expr)
 }
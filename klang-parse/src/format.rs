@@ -0,0 +1,415 @@
+use std::mem;
+
+use crate::{
+    error::{Expected, ParseError},
+    parser::{parse_syntax_with_settings, Associativity, ParserSettings},
+    prototype::DEFAULT_OPERATOR_PRECEDENCE,
+    syntax::{SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken, SyntaxTree},
+    token::Token,
+};
+
+/// Formats `input` with consistent indentation, spacing and line breaks.
+///
+/// Comments and parentheses are kept, and so is a single blank line where
+/// the source has blank lines. Sources with syntax errors are left alone,
+/// their errors are returned instead.
+pub fn format_source(input: &str) -> Result<String, Vec<ParseError>> {
+    format_source_with_settings(input, &mut ParserSettings::default())
+}
+
+/// Formats `input` like `format_source`, parsing it with `settings`.
+///
+/// Operators `input` uses without defining them may come from the files it
+/// imports, which the formatter doesn't read. They are taken as user-defined
+/// operators and kept as they are.
+pub fn format_source_with_settings(
+    input: &str,
+    settings: &mut ParserSettings,
+) -> Result<String, Vec<ParseError>> {
+    let tree = parse_assuming_operators(input, settings);
+    if !tree.errors.is_empty() {
+        return Err(tree.errors);
+    }
+
+    let mut formatter = Formatter {
+        source: input,
+        out: String::new(),
+        indent: 0,
+        separator: Separator::None,
+    };
+    formatter.items(&significant_children(&tree.root));
+    if !formatter.out.is_empty() {
+        formatter.out.push('\n');
+    }
+    Ok(formatter.out)
+}
+
+/// Parses `input`, adding the operators it fails to find to `settings` and
+/// parsing again until all of them are known.
+///
+/// The formatter writes the tokens in source order, so the precedence given
+/// to these operators doesn't change its output.
+fn parse_assuming_operators(input: &str, settings: &mut ParserSettings) -> SyntaxTree {
+    loop {
+        let mut attempt = settings.clone();
+        let tree = parse_syntax_with_settings(input, &mut attempt);
+        let mut assumed = false;
+        for error in &tree.errors {
+            match error {
+                ParseError::UnknownOperator { operator, .. } => {
+                    settings.add_binary_operator(
+                        operator,
+                        DEFAULT_OPERATOR_PRECEDENCE,
+                        Associativity::Left,
+                    );
+                    assumed = true;
+                }
+                ParseError::UnexpectedToken {
+                    expected: Expected::Expression,
+                    found: Token::Operator(operator),
+                    ..
+                } if !settings.is_unary_operator(operator)
+                    && settings.binary_operator(operator).is_none() =>
+                {
+                    settings.add_user_unary_operator(operator.as_ref());
+                    assumed = true;
+                }
+                _ => {}
+            }
+        }
+        if !assumed {
+            *settings = attempt;
+            return tree;
+        }
+    }
+}
+
+/// What to write in front of the next token.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+enum Separator {
+    None,
+    Space,
+    /// Line breaks, two leaving a blank line.
+    Lines(usize),
+}
+
+struct Formatter<'src> {
+    source: &'src str,
+    out: String,
+    indent: usize,
+    separator: Separator,
+}
+
+impl Formatter<'_> {
+    /// Asks for at least `separator` in front of the next token.
+    fn separate(&mut self, separator: Separator) {
+        self.separator = self.separator.max(separator);
+    }
+
+    fn write(&mut self, text: &str) {
+        let separator = mem::replace(&mut self.separator, Separator::None);
+        if !self.out.is_empty() {
+            match separator {
+                Separator::None => {}
+                Separator::Space => self.out.push(' '),
+                Separator::Lines(lines) => {
+                    for _ in 0..lines {
+                        self.out.push('\n');
+                    }
+                    for _ in 0..self.indent {
+                        self.out.push_str("    ");
+                    }
+                }
+            }
+        }
+        self.out.push_str(text);
+    }
+
+    /// Formats into a new buffer, one level deeper, and returns it.
+    fn nested(&mut self, format: impl FnOnce(&mut Self)) -> String {
+        let out = mem::take(&mut self.out);
+        let separator = mem::replace(&mut self.separator, Separator::None);
+        self.indent += 1;
+        format(self);
+        self.indent -= 1;
+        self.separator = separator;
+        mem::replace(&mut self.out, out)
+    }
+
+    /// Number of line breaks in the source between `offset` and the code
+    /// before it, capped to one blank line.
+    fn lines_before(&self, offset: usize) -> usize {
+        let before = &self.source[..offset];
+        let space = &before[before.trim_end().len()..];
+        space.matches('\n').count().min(2)
+    }
+
+    /// Writes `comment` at the end of the line of the code before it, if it
+    /// was there in the source, or on a line of its own.
+    fn comment(&mut self, comment: &SyntaxToken) {
+        match self.lines_before(comment.text_range().start) {
+            0 if self.separator < Separator::Lines(1) => self.separator = Separator::Space,
+            lines => self.separate(Separator::Lines(lines.max(1))),
+        }
+        self.write(comment.text().trim_end());
+        self.separate(Separator::Lines(1));
+    }
+
+    /// Writes the items and comments of a file or module, one per line.
+    fn items(&mut self, elements: &[SyntaxElement]) {
+        let mut previous = None;
+        // an own line comment already separated the next item from the previous one
+        let mut attached = false;
+        for (index, element) in elements.iter().enumerate() {
+            match element {
+                SyntaxElement::Node(item) => {
+                    let lines = self.lines_before(item.text_range().start);
+                    if attached {
+                        self.separate(Separator::Lines(lines.max(1)));
+                    } else {
+                        self.separate(item_separator(previous, Some(item.kind()), lines));
+                    }
+                    self.node(item);
+                    if is_delimited(item.kind()) {
+                        self.write(";");
+                    }
+                    previous = Some(item.kind());
+                    attached = false;
+                }
+                SyntaxElement::Token(comment) => {
+                    let lines = self.lines_before(comment.text_range().start);
+                    if lines > 0 && !attached {
+                        let next = elements[index..].iter().find_map(|element| match element {
+                            SyntaxElement::Node(item) => Some(item.kind()),
+                            SyntaxElement::Token(_) => None,
+                        });
+                        self.separate(item_separator(previous, next, lines));
+                        attached = true;
+                    }
+                    self.comment(comment);
+                }
+            }
+        }
+    }
+
+    fn node(&mut self, node: &SyntaxNode) {
+        match node.kind() {
+            SyntaxKind::Module => self.module(node),
+            SyntaxKind::BlockExpr => {
+                let is_function_body = node
+                    .parent()
+                    .is_some_and(|parent| parent.kind() == SyntaxKind::Function);
+                self.block(node, is_function_body);
+            }
+            _ => {
+                let mut previous = None;
+                // a comment after a `,` breaks the list, its next line is indented
+                let mut continued = false;
+                for element in significant_children(node) {
+                    let kind = element_kind(&element);
+                    if previous == Some(SyntaxKind::Comma)
+                        && !continued
+                        && starts_with_comment(&element)
+                    {
+                        self.indent += 1;
+                        continued = true;
+                    }
+                    if kind == SyntaxKind::Comment {
+                        if let SyntaxElement::Token(comment) = &element {
+                            self.comment(comment);
+                        }
+                        continue;
+                    }
+                    if previous
+                        .is_some_and(|previous| space_between(node.kind(), previous, &element))
+                    {
+                        self.separate(Separator::Space);
+                    }
+                    match &element {
+                        SyntaxElement::Node(child) => self.node(child),
+                        SyntaxElement::Token(token) => self.write(token.text()),
+                    }
+                    previous = Some(kind);
+                }
+                if continued {
+                    self.indent -= 1;
+                }
+            }
+        }
+    }
+
+    /// Writes `mod name {`, the module's items one level deeper, and `}`.
+    fn module(&mut self, module: &SyntaxNode) {
+        let elements = significant_children(module);
+        let Some(open) = elements
+            .iter()
+            .position(|element| element_kind(element) == SyntaxKind::OpeningBrace)
+        else {
+            return;
+        };
+        let close = elements.len() - 1;
+
+        for element in &elements[..=open] {
+            match element {
+                SyntaxElement::Token(token) if token.kind() == SyntaxKind::Comment => {
+                    self.comment(token)
+                }
+                SyntaxElement::Token(token) => {
+                    self.separate(Separator::Space);
+                    self.write(token.text());
+                }
+                SyntaxElement::Node(_) => {}
+            }
+        }
+        let items = &elements[open + 1..close];
+        if !items.is_empty() {
+            self.indent += 1;
+            self.separate(Separator::Lines(1));
+            self.items(items);
+            self.indent -= 1;
+            self.separate(Separator::Lines(1));
+        }
+        self.write("}");
+    }
+
+    /// Writes a block, on the line of its braces when it holds a single short
+    /// expression, unless `multiline` is set.
+    fn block(&mut self, block: &SyntaxNode, multiline: bool) {
+        let elements: Vec<_> = significant_children(block)
+            .into_iter()
+            .filter(|element| {
+                !matches!(
+                    element_kind(element),
+                    SyntaxKind::OpeningBrace | SyntaxKind::ClosingBrace
+                )
+            })
+            .collect();
+
+        self.write("{");
+        let mut single_line = None;
+        if let [SyntaxElement::Node(expr)] = &elements[..] {
+            let line = self.nested(|formatter| formatter.node(expr));
+            if !multiline && !line.contains('\n') {
+                self.separate(Separator::Space);
+                self.write(&line);
+                self.separate(Separator::Space);
+                self.write("}");
+                return;
+            }
+            single_line = Some(line);
+        }
+
+        let exprs = elements
+            .iter()
+            .filter(|element| matches!(element, SyntaxElement::Node(_)))
+            .count();
+        let mut written = 0;
+        self.indent += 1;
+        for element in &elements {
+            match element {
+                SyntaxElement::Node(expr) => {
+                    let lines = match written {
+                        0 => 1,
+                        _ => self.lines_before(expr.text_range().start).max(1),
+                    };
+                    self.separate(Separator::Lines(lines));
+                    match single_line.take() {
+                        Some(line) => self.write(&line),
+                        None => self.node(expr),
+                    }
+                    written += 1;
+                    if written < exprs {
+                        self.write(";");
+                    }
+                }
+                SyntaxElement::Token(comment) => self.comment(comment),
+            }
+        }
+        self.indent -= 1;
+        if !elements.is_empty() {
+            self.separate(Separator::Lines(1));
+        }
+        self.write("}");
+    }
+}
+
+/// Child nodes and tokens of `node`, leaving out whitespace and `;`, which
+/// the formatter writes itself.
+fn significant_children(node: &SyntaxNode) -> Vec<SyntaxElement> {
+    node.children_with_tokens()
+        .filter(|element| {
+            !matches!(
+                element_kind(element),
+                SyntaxKind::Whitespace | SyntaxKind::Delimiter
+            )
+        })
+        .collect()
+}
+
+fn element_kind(element: &SyntaxElement) -> SyntaxKind {
+    match element {
+        SyntaxElement::Node(node) => node.kind(),
+        SyntaxElement::Token(token) => token.kind(),
+    }
+}
+
+/// Kind of the first token of `element` that isn't trivia.
+fn first_token_kind(element: &SyntaxElement) -> Option<SyntaxKind> {
+    match element {
+        SyntaxElement::Node(node) => node
+            .children_with_tokens()
+            .find_map(|child| first_token_kind(&child)),
+        SyntaxElement::Token(token) if token.kind().is_trivia() => None,
+        SyntaxElement::Token(token) => Some(token.kind()),
+    }
+}
+
+/// Whether `element` starts with a comment, ahead of any other token.
+fn starts_with_comment(element: &SyntaxElement) -> bool {
+    match element {
+        SyntaxElement::Node(node) => node
+            .children_with_tokens()
+            .find(|child| element_kind(child) != SyntaxKind::Whitespace)
+            .is_some_and(|child| starts_with_comment(&child)),
+        SyntaxElement::Token(token) => token.kind() == SyntaxKind::Comment,
+    }
+}
+
+/// Whether a space goes between the `previous` child of a `parent` node and
+/// the `next` one.
+fn space_between(parent: SyntaxKind, previous: SyntaxKind, next: &SyntaxElement) -> bool {
+    use SyntaxKind::*;
+
+    match (parent, previous, element_kind(next)) {
+        (_, _, Comma | ClosingParenthesis | PathSeparator) => false,
+        (_, OpeningParenthesis | PathSeparator, _) => false,
+        // `binary| 5 (a, b)`
+        (Prototype, Number, ParameterList) => true,
+        (_, _, ParameterList | ArgumentList) => false,
+        (Prototype, Ident, Operator) => false,
+        // prefix operators stick to their operand, unless they would lex as one
+        (UnaryExpr, Operator, _) => first_token_kind(next) == Some(Operator),
+        _ => true,
+    }
+}
+
+/// Line breaks between two items, keeping items of the same kind together.
+fn item_separator(
+    previous: Option<SyntaxKind>,
+    next: Option<SyntaxKind>,
+    lines_before: usize,
+) -> Separator {
+    let lines = match (previous, next) {
+        (None, _) => return Separator::Lines(1),
+        (Some(SyntaxKind::Extern), Some(SyntaxKind::Extern))
+        | (Some(SyntaxKind::ImportDecl), Some(SyntaxKind::ImportDecl)) => 1,
+        (Some(previous), Some(next)) if previous.is_expr() && next.is_expr() => 1,
+        _ => 2,
+    };
+    Separator::Lines(lines.max(lines_before))
+}
+
+/// Whether items of this kind end with a `;`.
+fn is_delimited(kind: SyntaxKind) -> bool {
+    matches!(kind, SyntaxKind::Extern | SyntaxKind::ImportDecl) || kind.is_expr()
+}
@@ -1,39 +1,30 @@
 use crate::{
-    expect_token,
-    parse::{error, Parse, PartParsingResult},
-    parse_try,
+    expr::parse_block_expr,
+    parse::{Parse, Parser, PartParsingResult},
+    parser::ParserSettings,
+    syntax::SyntaxKind,
     token::Token,
 };
 use klang_ast::{
-    expr::Expression,
     function::{Function, Prototype},
     node::ASTNode,
 };
 
 impl Parse<ASTNode> for Function {
-    fn parse(tokens: &mut Vec<Token>) -> PartParsingResult<ASTNode> {
-        // Consume `fun` keyword.
-        tokens.pop();
-        let mut parsed_tokens = vec![Token::Fun];
-        let prototype_partial_parsing = Prototype::parse(tokens);
-        let prototype = parse_try!(prototype_partial_parsing, tokens, parsed_tokens);
-        expect_token!(
-            [Token::OpeningBrace, Token::OpeningBrace, ()] <= tokens,
-            parsed_tokens,
-            "'{' expected"
-        );
+    fn parse(parser: &mut Parser, settings: &ParserSettings) -> PartParsingResult<ASTNode> {
+        let marker = parser.start();
+        let is_public = parser.eat(&Token::Pub);
+        parser.expect(Token::Fun)?;
+        let prototype = Prototype::parse(parser, settings)?;
+        let body = Some(parse_block_expr(parser, settings, 0)?);
+        let (span, id) = parser.finish_node(marker, SyntaxKind::Function);
 
-        let expr_partial_parsing = Expression::parse(tokens);
-        let body = Some(parse_try!(expr_partial_parsing, tokens, parsed_tokens));
-        expect_token!(
-            [Token::ClosingBrace, Token::ClosingBrace, ()] <= tokens,
-            parsed_tokens,
-            "'}' expected"
-        );
-
-        PartParsingResult::Good(
-            ASTNode::FunctionNode(Function { prototype, body }),
-            parsed_tokens,
-        )
+        Ok(ASTNode::FunctionNode(Function {
+            is_public,
+            prototype,
+            body,
+            span,
+            id,
+        }))
     }
 }
@@ -0,0 +1,16 @@
+use crate::{
+    parse::{Parser, PartParsingResult},
+    syntax::SyntaxKind,
+};
+use klang_ast::node::{ASTNode, Import};
+
+/// Parses `import "path"`.
+pub(crate) fn parse_import(parser: &mut Parser) -> PartParsingResult<ASTNode> {
+    let marker = parser.start();
+    // Consume `import` keyword.
+    parser.bump();
+    let path = parser.expect_string()?.to_string();
+    let (span, id) = parser.finish_node(marker, SyntaxKind::ImportDecl);
+
+    Ok(ASTNode::ImportNode(Import { path, span, id }))
+}
@@ -1,79 +1,250 @@
-use crate::token::Token;
-
-#[allow(dead_code)]
-type TokenStream = Vec<Token>;
-
-#[allow(dead_code)]
-pub fn tokenize(input: &str) -> anyhow::Result<TokenStream> {
-    // regex for commentaries (start with #, end with the line end)
-    let comment_re = regex::Regex::new(r"(?m)#.*\n")?;
-    // remove commentaries from the input stream
-    let preprocessed = comment_re.replace_all(input, "\n");
-
-    let mut result = Vec::new();
-
-    // regex for token, just union of straightforward regexes for different token types
-    // operators are parsed the same way as identifier and separated later
-    let token_re = regex::Regex::new(concat!(
-        r"(?P<ident>\p{Alphabetic}\w*)|",
-        r"(?P<number>\d+\.?\d*)|",
-        r"(?P<delimiter>;)|",
-        r"(?P<oppar>\()|",
-        r"(?P<clpar>\))|",
-        r"(?P<opbrace>\{)|",
-        r"(?P<clbrace>\})|",
-        r"(?P<comma>,)|",
-        r"(?P<operator>\S)"
-    ))?;
-
-    for cap in token_re.captures_iter(&preprocessed) {
-        let token = if let Some(ident) = cap.name("ident") {
-            match ident.as_str() {
-                "fun" => Token::Fun,
-                "use" => Token::Use,
-                "if" => Token::If,
-                "else" => Token::Else,
-                _ => Token::Ident(ident.as_str().to_string()),
+use std::{borrow::Cow, fmt, iter::Peekable, str::CharIndices};
+
+use crate::token::{SpannedToken, Token};
+use klang_ast::span::{FileId, Span};
+
+type TokenStream<'src> = Vec<SpannedToken<'src>>;
+
+/// Operators spanning two characters, preferred over their one character prefix.
+const MULTI_CHAR_OPERATORS: [&str; 7] = ["==", "!=", "<=", ">=", "&&", "||", "->"];
+
+/// An error produced while turning source text into tokens.
+#[derive(PartialEq, Clone, Debug)]
+pub enum LexError {
+    /// A character that cannot start any token.
+    InvalidCharacter(char, Span),
+    /// A numeric literal that does not fit into an `f64`.
+    InvalidNumber(String, Span),
+    /// A string literal missing its closing `"`.
+    UnterminatedString(Span),
+}
+
+impl LexError {
+    pub fn span(&self) -> Span {
+        match self {
+            LexError::InvalidCharacter(_, span)
+            | LexError::InvalidNumber(_, span)
+            | LexError::UnterminatedString(span) => *span,
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::InvalidCharacter(ch, span) => {
+                write!(f, "{span}: invalid character {ch:?}")
             }
-        } else if let Some(number) = cap.name("number") {
-            match number.as_str().parse() {
-                Ok(number) => Token::Number(number),
-                Err(_) => anyhow::bail!("Lexer failed trying to parse number"),
+            LexError::InvalidNumber(number, span) => {
+                write!(f, "{span}: invalid number literal `{number}`")
             }
-        } else if cap.name("delimiter").is_some() {
-            Token::Delimiter
-        } else if cap.name("oppar").is_some() {
-            Token::OpeningParenthesis
-        } else if cap.name("clpar").is_some() {
-            Token::ClosingParenthesis
-        } else if cap.name("comma").is_some() {
-            Token::Comma
-        } else if cap.name("opbrace").is_some() {
-            Token::OpeningBrace
-        } else if cap.name("clbrace").is_some() {
-            Token::ClosingBrace
+            LexError::UnterminatedString(span) => {
+                write!(f, "{span}: unterminated string literal")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// Character-driven lexer yielding the tokens of `input` one at a time.
+///
+/// Whitespace and `#` comments are skipped. After an error the lexer resumes
+/// with the next character, so every invalid character can be reported.
+pub struct Lexer<'a> {
+    file: FileId,
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    /// Offset of `input` in the file, added to every span.
+    base_offset: usize,
+    line: u32,
+    column: u32,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(file: FileId, input: &'a str) -> Self {
+        Self {
+            file,
+            input,
+            chars: input.char_indices().peekable(),
+            base_offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    /// Treats `input` as the part of the file starting at `offset`, on the
+    /// given 1-based `line` and `column`.
+    pub fn starting_at(mut self, offset: usize, line: u32, column: u32) -> Self {
+        self.base_offset = offset;
+        self.line = line;
+        self.column = column;
+        self
+    }
+
+    /// Consumes the next character, keeping line and column up to date.
+    fn bump(&mut self) -> Option<(usize, char)> {
+        let (offset, ch) = self.chars.next()?;
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
         } else {
-            let operator = cap
-                .name("operator")
-                .ok_or_else(|| anyhow::anyhow!("lexer failed trying to get operator"))?;
-            Token::Operator(operator.as_str().to_string())
+            self.column += 1;
+        }
+        Some((offset, ch))
+    }
+
+    /// Consumes characters while `predicate` holds, returning the end offset.
+    fn bump_while(&mut self, predicate: impl Fn(char) -> bool) -> usize {
+        while let Some(&(_, ch)) = self.chars.peek() {
+            if !predicate(ch) {
+                break;
+            }
+            self.bump();
+        }
+        self.offset()
+    }
+
+    /// Byte offset of the next unconsumed character.
+    fn offset(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map_or(self.input.len(), |&(offset, _)| offset)
+    }
+
+    /// Span from `start` up to the next unconsumed character.
+    fn span_from(&mut self, start: usize, line: u32, column: u32) -> Span {
+        Span {
+            file: self.file,
+            start: self.base_offset + start,
+            end: self.base_offset + self.offset(),
+            line,
+            column,
+        }
+    }
+
+    fn peek_char(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, ch)| ch)
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<SpannedToken<'a>, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // skip whitespace and commentaries (start with #, end with the line end)
+        loop {
+            match self.peek_char()? {
+                ch if ch.is_whitespace() => {
+                    self.bump();
+                }
+                '#' => {
+                    self.bump_while(|ch| ch != '\n');
+                }
+                _ => break,
+            }
+        }
+
+        let (line, column) = (self.line, self.column);
+        let (start, ch) = self.bump()?;
+
+        let token = match ch {
+            ';' => Token::Delimiter,
+            '(' => Token::OpeningParenthesis,
+            ')' => Token::ClosingParenthesis,
+            '{' => Token::OpeningBrace,
+            '}' => Token::ClosingBrace,
+            ',' => Token::Comma,
+            ':' if self.peek_char() == Some(':') => {
+                self.bump();
+                Token::PathSeparator
+            }
+            ch if ch.is_alphabetic() => {
+                let end = self.bump_while(|ch| ch.is_alphanumeric() || ch == '_');
+                match &self.input[start..end] {
+                    "fun" => Token::Fun,
+                    "pub" => Token::Pub,
+                    "use" => Token::Use,
+                    "import" => Token::Import,
+                    "mod" => Token::Mod,
+                    "if" => Token::If,
+                    "else" => Token::Else,
+                    "var" => Token::Var,
+                    "in" => Token::In,
+                    "for" => Token::For,
+                    "while" => Token::While,
+                    ident => Token::Ident(Cow::Borrowed(ident)),
+                }
+            }
+            ch if ch.is_ascii_digit() => {
+                self.bump_while(|ch| ch.is_ascii_digit());
+                if self.peek_char() == Some('.') {
+                    self.bump();
+                    self.bump_while(|ch| ch.is_ascii_digit());
+                }
+                let number = &self.input[start..self.offset()];
+                match number.parse() {
+                    Ok(number) => Token::Number(number),
+                    Err(_) => {
+                        let span = self.span_from(start, line, column);
+                        return Some(Err(LexError::InvalidNumber(number.to_string(), span)));
+                    }
+                }
+            }
+            // strings have no escapes, they end at the next `"`
+            '"' => {
+                let end = self.bump_while(|ch| ch != '"');
+                if self.bump().is_none() {
+                    let span = self.span_from(start, line, column);
+                    return Some(Err(LexError::UnterminatedString(span)));
+                }
+                Token::String(Cow::Borrowed(&self.input[start + 1..end]))
+            }
+            ch if ch.is_ascii_punctuation() => {
+                if let Some(next) = self.peek_char() {
+                    let end = start + ch.len_utf8() + next.len_utf8();
+                    if MULTI_CHAR_OPERATORS.contains(&&self.input[start..end]) {
+                        self.bump();
+                    }
+                }
+                Token::Operator(Cow::Borrowed(&self.input[start..self.offset()]))
+            }
+            ch => {
+                let span = self.span_from(start, line, column);
+                return Some(Err(LexError::InvalidCharacter(ch, span)));
+            }
         };
 
-        result.push(token)
+        let span = self.span_from(start, line, column);
+        Some(Ok(SpannedToken { token, span }))
     }
+}
+
+pub fn tokenize(input: &str) -> anyhow::Result<TokenStream<'_>> {
+    tokenize_file(FileId::default(), input)
+}
 
-    Ok(result)
+/// Tokenizes `input`, attributing every token's span to `file`.
+pub fn tokenize_file(file: FileId, input: &str) -> anyhow::Result<TokenStream<'_>> {
+    Ok(Lexer::new(file, input).collect::<Result<_, _>>()?)
 }
 
 #[cfg(test)]
 mod test {
-    use super::tokenize;
-    use crate::token::Token;
+    use std::borrow::Cow;
+
+    use super::{tokenize, tokenize_file, LexError, Lexer};
+    use crate::token::{SpannedToken, Token};
+    use klang_ast::span::{FileId, Span};
+
+    fn kinds(token_stream: Vec<SpannedToken>) -> Vec<Token> {
+        token_stream.into_iter().map(|t| t.token).collect()
+    }
 
     #[test]
     fn test_lex_pub_keyword() {
         let input_str = r#"use"#;
-        let token_stream = tokenize(input_str).unwrap();
+        let token_stream = kinds(tokenize(input_str).unwrap());
         let expected = vec![Token::Use];
         assert_eq!(token_stream, expected)
     }
@@ -81,7 +252,7 @@ mod test {
     #[test]
     fn test_lex_fun_keyword() {
         let input_str = r#"fun"#;
-        let token_stream = tokenize(input_str).unwrap();
+        let token_stream = kinds(tokenize(input_str).unwrap());
         let expected = vec![Token::Fun];
         assert_eq!(token_stream, expected)
     }
@@ -89,12 +260,12 @@ mod test {
     #[test]
     fn test_lex_ident() {
         let input_str = r#"this is a ident"#;
-        let token_stream = tokenize(input_str).unwrap();
+        let token_stream = kinds(tokenize(input_str).unwrap());
         let expected = vec![
-            Token::Ident("this".to_string()),
-            Token::Ident("is".to_string()),
-            Token::Ident("a".to_string()),
-            Token::Ident("ident".to_string()),
+            Token::Ident("this".into()),
+            Token::Ident("is".into()),
+            Token::Ident("a".into()),
+            Token::Ident("ident".into()),
         ];
         assert_eq!(token_stream, expected)
     }
@@ -102,7 +273,7 @@ mod test {
     #[test]
     fn test_lex_paranthesis() {
         let input_str = r#"()"#;
-        let token_stream = tokenize(input_str).unwrap();
+        let token_stream = kinds(tokenize(input_str).unwrap());
         let expected = vec![Token::OpeningParenthesis, Token::ClosingParenthesis];
         assert_eq!(token_stream, expected)
     }
@@ -110,7 +281,7 @@ mod test {
     #[test]
     fn test_lex_braces() {
         let input_str = r#"{}"#;
-        let token_stream = tokenize(input_str).unwrap();
+        let token_stream = kinds(tokenize(input_str).unwrap());
         let expected = vec![Token::OpeningBrace, Token::ClosingBrace];
         assert_eq!(token_stream, expected)
     }
@@ -118,10 +289,10 @@ mod test {
     #[test]
     fn test_lex_fun_decl() {
         let input_str = r#"fun this_is_a_decl() {}"#;
-        let token_stream = tokenize(input_str).unwrap();
+        let token_stream = kinds(tokenize(input_str).unwrap());
         let expected = vec![
             Token::Fun,
-            Token::Ident("this_is_a_decl".to_string()),
+            Token::Ident("this_is_a_decl".into()),
             Token::OpeningParenthesis,
             Token::ClosingParenthesis,
             Token::OpeningBrace,
@@ -133,7 +304,7 @@ mod test {
     #[test]
     fn test_lex_number() {
         let input_str = r#"102"#;
-        let token_stream = tokenize(input_str).unwrap();
+        let token_stream = kinds(tokenize(input_str).unwrap());
         let expected = vec![Token::Number(102.0)];
         assert_eq!(token_stream, expected)
     }
@@ -141,10 +312,10 @@ mod test {
     #[test]
     fn test_if_else_stmnt() {
         let input_str = r#"if a {} else {}"#;
-        let token_stream = tokenize(input_str).unwrap();
+        let token_stream = kinds(tokenize(input_str).unwrap());
         let expected = vec![
             Token::If,
-            Token::Ident("a".to_string()),
+            Token::Ident("a".into()),
             Token::OpeningBrace,
             Token::ClosingBrace,
             Token::Else,
@@ -153,4 +324,204 @@ mod test {
         ];
         assert_eq!(token_stream, expected)
     }
+
+    #[test]
+    fn test_lex_borrows_source() {
+        let input_str = "kaya <= 1";
+        let token_stream = tokenize(input_str).unwrap();
+        for token in token_stream {
+            if let Token::Ident(text) | Token::Operator(text) = token.token {
+                assert!(matches!(text, Cow::Borrowed(_)));
+                assert_eq!(text, &input_str[token.span.start..token.span.end]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lex_spans() {
+        let input_str = "fun f() {\n  x # comment\n}";
+        let token_stream = tokenize_file(FileId(3), input_str).unwrap();
+        let spans: Vec<_> = token_stream
+            .iter()
+            .map(|t| (t.span.start, t.span.end, t.span.line, t.span.column))
+            .collect();
+        let expected = vec![
+            (0, 3, 1, 1),
+            (4, 5, 1, 5),
+            (5, 6, 1, 6),
+            (6, 7, 1, 7),
+            (8, 9, 1, 9),
+            (12, 13, 2, 3),
+            (24, 25, 3, 1),
+        ];
+        assert_eq!(spans, expected);
+        assert!(token_stream.iter().all(|t| t.span.file == FileId(3)));
+    }
+
+    #[test]
+    fn test_lex_spans_utf8() {
+        let input_str = "# héllo wörld\nçé + 1";
+        let token_stream = tokenize(input_str).unwrap();
+        let expected = vec![
+            SpannedToken {
+                token: Token::Ident("çé".into()),
+                span: Span {
+                    file: FileId::default(),
+                    start: 16,
+                    end: 20,
+                    line: 2,
+                    column: 1,
+                },
+            },
+            SpannedToken {
+                token: Token::Operator("+".into()),
+                span: Span {
+                    file: FileId::default(),
+                    start: 21,
+                    end: 22,
+                    line: 2,
+                    column: 4,
+                },
+            },
+            SpannedToken {
+                token: Token::Number(1.0),
+                span: Span {
+                    file: FileId::default(),
+                    start: 23,
+                    end: 24,
+                    line: 2,
+                    column: 6,
+                },
+            },
+        ];
+        assert_eq!(token_stream, expected)
+    }
+
+    #[test]
+    fn test_lex_comment_at_end_of_file() {
+        let input_str = "x # trailing comment without newline";
+        let token_stream = kinds(tokenize(input_str).unwrap());
+        let expected = vec![Token::Ident("x".into())];
+        assert_eq!(token_stream, expected)
+    }
+
+    #[test]
+    fn test_lex_invalid_character() {
+        let input_str = "x € y";
+        let errors: Vec<_> = Lexer::new(FileId::default(), input_str)
+            .filter_map(Result::err)
+            .collect();
+        let expected = vec![LexError::InvalidCharacter(
+            '€',
+            Span {
+                file: FileId::default(),
+                start: 2,
+                end: 5,
+                line: 1,
+                column: 3,
+            },
+        )];
+        assert_eq!(errors, expected);
+        assert!(tokenize(input_str).is_err());
+    }
+
+    #[test]
+    fn test_lex_strings() {
+        let input_str = r#"import "lib/math.kl" "" "unterminated"#;
+        let mut lexer = Lexer::new(FileId::default(), input_str);
+        assert_eq!(lexer.next().unwrap().unwrap().token, Token::Import);
+        let path = lexer.next().unwrap().unwrap();
+        assert_eq!(path.token, Token::String("lib/math.kl".into()));
+        assert_eq!((path.span.start, path.span.end), (7, 20));
+        assert_eq!(
+            lexer.next().unwrap().unwrap().token,
+            Token::String("".into())
+        );
+        assert!(matches!(
+            lexer.next(),
+            Some(Err(LexError::UnterminatedString(Span {
+                start: 24,
+                end: 37,
+                ..
+            })))
+        ));
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn test_lex_qualified_names() {
+        let input_str = r#"mod m { } m::f(a:b)"#;
+        let token_stream = kinds(tokenize(input_str).unwrap());
+        let expected = vec![
+            Token::Mod,
+            Token::Ident("m".into()),
+            Token::OpeningBrace,
+            Token::ClosingBrace,
+            Token::Ident("m".into()),
+            Token::PathSeparator,
+            Token::Ident("f".into()),
+            Token::OpeningParenthesis,
+            Token::Ident("a".into()),
+            Token::Operator(":".into()),
+            Token::Ident("b".into()),
+            Token::ClosingParenthesis,
+        ];
+        assert_eq!(token_stream, expected)
+    }
+
+    #[test]
+    fn test_lex_is_streaming() {
+        let mut lexer = Lexer::new(FileId::default(), "fun f");
+        assert_eq!(lexer.next().unwrap().unwrap().token, Token::Fun);
+        assert_eq!(
+            lexer.next().unwrap().unwrap().token,
+            Token::Ident("f".into())
+        );
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn test_lex_multi_char_operators() {
+        let input_str = r#"a<=b == c != d >= e && f || g -> h < -i"#;
+        let token_stream = kinds(tokenize(input_str).unwrap());
+        let ident = |name: &'static str| Token::Ident(name.into());
+        let operator = |op: &'static str| Token::Operator(op.into());
+        let expected = vec![
+            ident("a"),
+            operator("<="),
+            ident("b"),
+            operator("=="),
+            ident("c"),
+            operator("!="),
+            ident("d"),
+            operator(">="),
+            ident("e"),
+            operator("&&"),
+            ident("f"),
+            operator("||"),
+            ident("g"),
+            operator("->"),
+            ident("h"),
+            operator("<"),
+            operator("-"),
+            ident("i"),
+        ];
+        assert_eq!(token_stream, expected)
+    }
+
+    #[test]
+    fn test_lex_operator_longest_match() {
+        let input_str = r#"<<= == --> - >"#;
+        let token_stream = kinds(tokenize(input_str).unwrap());
+        let expected = vec![
+            Token::Operator("<".into()),
+            Token::Operator("<=".into()),
+            Token::Operator("==".into()),
+            Token::Operator("-".into()),
+            Token::Operator("->".into()),
+            Token::Operator("-".into()),
+            Token::Operator(">".into()),
+        ];
+        assert_eq!(token_stream, expected)
+    }
 }
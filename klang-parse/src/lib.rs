@@ -1,278 +1,2111 @@
+pub mod error;
+mod event;
 mod expr;
+pub mod format;
 mod function;
+mod import;
 pub mod lexer;
+mod lower;
+mod module;
 mod parse;
 pub mod parser;
 mod prototype;
+pub mod session;
+pub mod syntax;
 pub mod token;
 mod r#use;
 
 #[cfg(test)]
 mod test {
     use klang_ast::{
-        expr::Expression,
-        function::{Function, Prototype},
-        node::ASTNode,
+        expr::{Expression, ExpressionKind},
+        fold::{self, Fold},
+        function::{Function, Prototype, PrototypeKind},
+        id::NodeId,
+        node::{ASTNode, Import, Module},
+        sexpr::SExpr,
+        spanless::SpanlessEq,
+        visit::{walk_expr, walk_expr_mut, Visitor, VisitorMut},
     };
 
-    use crate::{lexer, token::Token};
+    use klang_ast::span::{FileId, Span};
 
-    use super::parser::parse;
+    use crate::{
+        error::{Expected, ParseError},
+        format::format_source,
+        lexer,
+        parse::{Parser, ParsingResult},
+        session::ParseSession,
+        syntax::{SyntaxElement, SyntaxKind},
+        token::{SpannedToken, Token},
+    };
+
+    use super::parser::{
+        parse, parse_items, parse_syntax, parse_syntax_file, parse_to_import, parse_with_settings,
+        Associativity, ParserSettings,
+    };
+
+    /// Asserts two AST values are equal, ignoring their spans and ids.
+    macro_rules! assert_spanless_eq {
+        ($left:expr, $right:expr $(,)?) => {{
+            let (left, right) = (&$left, &$right);
+            assert!(
+                left.spanless_eq(right),
+                "left: {:#?}\nright: {:#?}",
+                left,
+                right
+            );
+        }};
+    }
+
+    impl SpanlessEq for Token<'_> {
+        fn spanless_eq(&self, other: &Self) -> bool {
+            self == other
+        }
+    }
+
+    /// An expression without span and id, to compare against parsed ones.
+    fn expr(kind: ExpressionKind) -> Expression {
+        Expression::dummy(kind)
+    }
+
+    fn strip_spans<'src>(
+        parse_result: ParsingResult<'_, 'src>,
+    ) -> (Vec<ASTNode>, Vec<Token<'src>>) {
+        let (tree, left_tokens) = parse_result.unwrap();
+        let left_tokens = left_tokens
+            .iter()
+            .map(|SpannedToken { token, .. }| token.clone())
+            .collect();
+        (tree, left_tokens)
+    }
 
     #[test]
     fn parse_function_definition() {
         let input_str = r#"use kaya();"#;
         let token_stream = lexer::tokenize(input_str).unwrap();
-        let parse_result = parse(&token_stream, &[]).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
         let expected_tree = vec![ASTNode::ExternNode(Prototype {
             name: "kaya".to_string(),
             args: vec![],
+            kind: PrototypeKind::Function,
+            span: Span::default(),
+            id: NodeId::DUMMY,
         })];
 
         let left_tokens = vec![];
         let expected_result = (expected_tree, left_tokens);
 
-        assert_eq!(parse_result, expected_result)
+        assert_spanless_eq!(parse_result, expected_result)
     }
 
     #[test]
     fn parse_function_definition_with_leftover_tokens() {
         let input_str = r#"use kaya(); use"#;
         let token_stream = lexer::tokenize(input_str).unwrap();
-        let parse_result = parse(&token_stream, &[]).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
         let expected_tree = vec![ASTNode::ExternNode(Prototype {
             name: "kaya".to_string(),
             args: vec![],
+            kind: PrototypeKind::Function,
+            span: Span::default(),
+            id: NodeId::DUMMY,
         })];
 
         let left_tokens = vec![Token::Use];
         let expected_result = (expected_tree, left_tokens);
 
-        assert_eq!(parse_result, expected_result)
+        assert_spanless_eq!(parse_result, expected_result)
     }
 
     #[test]
     fn parse_function_declaration() {
         let input_str = r#"fun kaya() { 5 }"#;
         let token_stream = lexer::tokenize(input_str).unwrap();
-        let parse_result = parse(&token_stream, &[]).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
         let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
             prototype: Prototype {
                 name: "kaya".to_string(),
                 args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
             },
-            body: Some(Expression::Literal(5.0)),
+            body: Some(expr(ExpressionKind::Literal(5.0))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
         })];
 
         let left_tokens = vec![];
         let expected_result = (expected_tree, left_tokens);
 
-        assert_eq!(parse_result, expected_result)
+        assert_spanless_eq!(parse_result, expected_result)
     }
 
     #[test]
     fn parse_function_declaration_with_leftover_tokens() {
         let input_str = r#"fun kaya() { 5 } fun"#;
         let token_stream = lexer::tokenize(input_str).unwrap();
-        let parse_result = parse(&token_stream, &[]).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
         let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
             prototype: Prototype {
                 name: "kaya".to_string(),
                 args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
             },
-            body: Some(Expression::Literal(5.0)),
+            body: Some(expr(ExpressionKind::Literal(5.0))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
         })];
 
         let left_tokens = vec![Token::Fun];
         let expected_result = (expected_tree, left_tokens);
 
-        assert_eq!(parse_result, expected_result)
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    #[test]
+    fn parse_imports() {
+        let input_str = r#"import "lib/math.kl"; use sin(x) import "util.kl""#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
+        let expected_tree = vec![
+            ASTNode::ImportNode(Import {
+                path: "lib/math.kl".to_string(),
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            }),
+            ASTNode::ExternNode(Prototype {
+                name: "sin".to_string(),
+                args: vec!["x".to_string()],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            }),
+            ASTNode::ImportNode(Import {
+                path: "util.kl".to_string(),
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            }),
+        ];
+        assert_spanless_eq!(parse_result, (expected_tree.clone(), vec![]));
+        assert_spanless_eq!(parse_syntax(input_str).lower().nodes, expected_tree);
+
+        let token_stream = lexer::tokenize("import math").unwrap();
+        let failure = parse(&token_stream, &[]).unwrap_err();
+        assert!(matches!(
+            &failure.errors[..],
+            [ParseError::UnexpectedToken {
+                expected: Expected::String,
+                found: Token::Ident(_),
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn parse_stops_after_imports() {
+        let token_stream = lexer::tokenize(r#"use sin(x) import "op.kl" 1 | 2"#).unwrap();
+        let mut settings = ParserSettings::default();
+        let (nodes, left_tokens) = parse_to_import(&token_stream, &mut settings).unwrap();
+        assert!(matches!(
+            &nodes[..],
+            [ASTNode::ExternNode(_), ASTNode::ImportNode(Import { path, .. })] if path == "op.kl"
+        ));
+        assert!(parse_to_import(left_tokens, &mut settings.clone()).is_err());
+
+        let token_stream = lexer::tokenize("mod m { fun binary| 5 (a, b) { a } }").unwrap();
+        let (imported, _) = parse(&token_stream, &[]).unwrap();
+        settings.register_operators(&imported);
+        let (nodes, left_tokens) = parse_to_import(left_tokens, &mut settings).unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert!(left_tokens.is_empty());
+    }
+
+    #[test]
+    fn parse_modules() {
+        let input_str = r#"
+            mod math {
+                fun sq(x) { x * x }
+                mod float { pub fun sqrt(x) { x } }
+            }
+            math::float::sqrt(math::sq(2))
+        "#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
+        let expected_tree = vec![
+            ASTNode::ModuleNode(Module {
+                name: "math".to_string(),
+                items: vec![
+                    ASTNode::FunctionNode(Function {
+                        is_public: false,
+                        prototype: Prototype {
+                            name: "sq".to_string(),
+                            args: vec!["x".to_string()],
+                            kind: PrototypeKind::Function,
+                            span: Span::default(),
+                            id: NodeId::DUMMY,
+                        },
+                        body: Some(expr(ExpressionKind::Binary(
+                            "*".to_string(),
+                            Box::new(expr(ExpressionKind::Variable("x".to_string()))),
+                            Box::new(expr(ExpressionKind::Variable("x".to_string()))),
+                        ))),
+                        span: Span::default(),
+                        id: NodeId::DUMMY,
+                    }),
+                    ASTNode::ModuleNode(Module {
+                        name: "float".to_string(),
+                        items: vec![ASTNode::FunctionNode(Function {
+                            is_public: true,
+                            prototype: Prototype {
+                                name: "sqrt".to_string(),
+                                args: vec!["x".to_string()],
+                                kind: PrototypeKind::Function,
+                                span: Span::default(),
+                                id: NodeId::DUMMY,
+                            },
+                            body: Some(expr(ExpressionKind::Variable("x".to_string()))),
+                            span: Span::default(),
+                            id: NodeId::DUMMY,
+                        })],
+                        span: Span::default(),
+                        id: NodeId::DUMMY,
+                    }),
+                ],
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            }),
+            ASTNode::FunctionNode(Function {
+                is_public: false,
+                prototype: Prototype {
+                    name: "".to_string(),
+                    args: vec![],
+                    kind: PrototypeKind::Function,
+                    span: Span::default(),
+                    id: NodeId::DUMMY,
+                },
+                body: Some(expr(ExpressionKind::Call(
+                    "math::float::sqrt".into(),
+                    vec![expr(ExpressionKind::Call(
+                        "math::sq".into(),
+                        vec![expr(ExpressionKind::Literal(2.0))],
+                    ))],
+                ))),
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            }),
+        ];
+        assert_spanless_eq!(parse_result, (expected_tree.clone(), vec![]));
+        assert_spanless_eq!(parse_syntax(input_str).lower().nodes, expected_tree);
+    }
+
+    #[test]
+    fn parse_public_functions() {
+        let input_str = r#"pub fun one() { 1 } mod m { pub fun two() { 2 } fun three() { 3 } }"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let (tree, _) = strip_spans(parse(&token_stream, &[]));
+        let mut visibility = vec![];
+        for node in tree.iter().chain(match &tree[1] {
+            ASTNode::ModuleNode(module) => &module.items,
+            node => panic!("expected a module, found {node:?}"),
+        }) {
+            if let ASTNode::FunctionNode(function) = node {
+                visibility.push((function.prototype.name.as_str(), function.is_public));
+            }
+        }
+        assert_eq!(
+            visibility,
+            vec![("one", true), ("two", true), ("three", false)]
+        );
+        assert_spanless_eq!(parse_syntax(input_str).lower().nodes, tree);
+
+        let token_stream = lexer::tokenize("pub use sin(x)").unwrap();
+        let failure = parse(&token_stream, &[]).unwrap_err();
+        assert!(matches!(
+            &failure.errors[..],
+            [ParseError::UnexpectedToken {
+                expected: Expected::Token(Token::Fun),
+                found: Token::Use,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn parse_module_errors() {
+        let expected_after = |input_str: &str| {
+            let token_stream = lexer::tokenize(input_str).unwrap();
+            match &parse(&token_stream, &[]).unwrap_err().errors[..] {
+                [ParseError::UnexpectedToken { expected, .. }] => expected.clone(),
+                errors => panic!("unexpected errors {errors:?}"),
+            }
+        };
+
+        // modules hold items, not expressions
+        let expected = expected_after("mod math { 1 + 2 }");
+        assert_eq!(expected.to_string(), "`fun`, `pub`, `mod` or `}`");
+        // externs are declared at the top level
+        let token_stream = lexer::tokenize("mod m { use sin(x) }").unwrap();
+        let failure = parse(&token_stream, &[]).unwrap_err();
+        assert!(matches!(
+            &failure.errors[..],
+            [ParseError::UnexpectedToken {
+                found: Token::Use,
+                span: Span { start: 8, .. },
+                ..
+            }]
+        ));
+        // only functions can be qualified
+        let expected = expected_after("math::pi + 1");
+        assert_eq!(expected, Expected::Token(Token::OpeningParenthesis));
+    }
+
+    #[test]
+    fn parse_module_recovers_from_errors() {
+        let input_str = "mod m { fun f() { 1 + } fun g() { 2 } } fun h() { 3 }";
+        let expected = "(mod m (fun g () 2))\n(fun h () 3)\n";
+
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let failure = parse(&token_stream, &[]).unwrap_err();
+        assert_eq!(failure.errors.len(), 1, "{:?}", failure.errors);
+        assert_eq!(SExpr(&failure.nodes[..]).to_string(), expected);
+
+        let tree = parse_syntax(input_str);
+        assert_eq!(tree.errors, failure.errors);
+        let lowered = tree.lower();
+        assert_eq!(SExpr(&lowered.nodes[..]).to_string(), expected);
+        assert_eq!(lowered.dropped, 1);
+
+        // a broken item missing its `}` leaves the module's `}` to the module
+        let token_stream = lexer::tokenize("mod m { fun f( } fun h() { 3 }").unwrap();
+        let failure = parse(&token_stream, &[]).unwrap_err();
+        assert_eq!(failure.errors.len(), 1, "{:?}", failure.errors);
+        assert_eq!(
+            SExpr(&failure.nodes[..]).to_string(),
+            "(mod m)\n(fun h () 3)\n"
+        );
     }
 
     #[test]
     fn parse_expr_literal() {
         let input_str = r#"5"#;
         let token_stream = lexer::tokenize(input_str).unwrap();
-        let parse_result = parse(&token_stream, &[]).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
         let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
             prototype: Prototype {
                 name: "".to_string(),
                 args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
             },
-            body: Some(Expression::Literal(5.0)),
+            body: Some(expr(ExpressionKind::Literal(5.0))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
         })];
 
         let left_tokens = vec![];
         let expected_result = (expected_tree, left_tokens);
 
-        assert_eq!(parse_result, expected_result)
+        assert_spanless_eq!(parse_result, expected_result)
     }
 
     #[test]
     fn parse_expr_literal_with_leftover_tokens() {
         let input_str = r#"5 fun"#;
         let token_stream = lexer::tokenize(input_str).unwrap();
-        let parse_result = parse(&token_stream, &[]).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
         let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
             prototype: Prototype {
                 name: "".to_string(),
                 args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
             },
-            body: Some(Expression::Literal(5.0)),
+            body: Some(expr(ExpressionKind::Literal(5.0))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
         })];
 
         let left_tokens = vec![Token::Fun];
         let expected_result = (expected_tree, left_tokens);
 
-        assert_eq!(parse_result, expected_result)
+        assert_spanless_eq!(parse_result, expected_result)
     }
 
     #[test]
     fn parse_expr_variable() {
         let input_str = r#"x"#;
         let token_stream = lexer::tokenize(input_str).unwrap();
-        let parse_result = parse(&token_stream, &[]).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
         let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
             prototype: Prototype {
                 name: "".to_string(),
                 args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
             },
-            body: Some(Expression::Variable("x".to_string())),
+            body: Some(expr(ExpressionKind::Variable("x".to_string()))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
         })];
 
         let left_tokens = vec![];
         let expected_result = (expected_tree, left_tokens);
 
-        assert_eq!(parse_result, expected_result)
+        assert_spanless_eq!(parse_result, expected_result)
     }
 
     #[test]
     fn parse_expr_variable_with_leftover_tokens() {
         let input_str = r#"x fun"#;
         let token_stream = lexer::tokenize(input_str).unwrap();
-        let parse_result = parse(&token_stream, &[]).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
         let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
             prototype: Prototype {
                 name: "".to_string(),
                 args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
             },
-            body: Some(Expression::Variable("x".to_string())),
+            body: Some(expr(ExpressionKind::Variable("x".to_string()))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
         })];
 
         let left_tokens = vec![Token::Fun];
         let expected_result = (expected_tree, left_tokens);
 
-        assert_eq!(parse_result, expected_result)
+        assert_spanless_eq!(parse_result, expected_result)
     }
 
     #[test]
     fn parse_expr_call() {
         let input_str = r#"x()"#;
         let token_stream = lexer::tokenize(input_str).unwrap();
-        let parse_result = parse(&token_stream, &[]).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
         let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
             prototype: Prototype {
                 name: "".to_string(),
                 args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
             },
-            body: Some(Expression::Call("x".to_string(), vec![])),
+            body: Some(expr(ExpressionKind::Call("x".into(), vec![]))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
         })];
 
         let left_tokens = vec![];
         let expected_result = (expected_tree, left_tokens);
 
-        assert_eq!(parse_result, expected_result)
+        assert_spanless_eq!(parse_result, expected_result)
     }
 
     #[test]
     fn parse_expr_call_with_leftover_tokens() {
         let input_str = r#"x() use"#;
         let token_stream = lexer::tokenize(input_str).unwrap();
-        let parse_result = parse(&token_stream, &[]).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
         let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
             prototype: Prototype {
                 name: "".to_string(),
                 args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
             },
-            body: Some(Expression::Call("x".to_string(), vec![])),
+            body: Some(expr(ExpressionKind::Call("x".into(), vec![]))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
         })];
 
         let left_tokens = vec![Token::Use];
         let expected_result = (expected_tree, left_tokens);
 
-        assert_eq!(parse_result, expected_result)
+        assert_spanless_eq!(parse_result, expected_result)
     }
 
     #[test]
     fn parse_expr_literal_binary() {
         let input_str = r#"5 + 4 * 2"#;
         let token_stream = lexer::tokenize(input_str).unwrap();
-        let parse_result = parse(&token_stream, &[]).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
         let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
             prototype: Prototype {
                 name: "".to_string(),
                 args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
             },
-            body: Some(Expression::Binary(
+            body: Some(expr(ExpressionKind::Binary(
                 "+".to_string(),
-                Box::new(Expression::Literal(5.0)),
-                Box::new(Expression::Binary(
+                Box::new(expr(ExpressionKind::Literal(5.0))),
+                Box::new(expr(ExpressionKind::Binary(
                     "*".to_string(),
-                    Box::new(Expression::Literal(4.0)),
-                    Box::new(Expression::Literal(2.0)),
-                )),
-            )),
+                    Box::new(expr(ExpressionKind::Literal(4.0))),
+                    Box::new(expr(ExpressionKind::Literal(2.0))),
+                ))),
+            ))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
         })];
 
         let left_tokens = vec![];
         let expected_result = (expected_tree, left_tokens);
 
-        assert_eq!(parse_result, expected_result)
+        assert_spanless_eq!(parse_result, expected_result)
     }
 
     #[test]
     fn parse_expr_conditional() {
         let input_str = r#"if 5 { 1 } else {2}"#;
         let token_stream = lexer::tokenize(input_str).unwrap();
-        let parse_result = parse(&token_stream, &[]).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
         let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
             prototype: Prototype {
                 name: "".to_string(),
                 args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
             },
-            body: Some(Expression::Conditional {
-                cond_expr: Box::new(Expression::Literal(5.0)),
-                if_block_expr: Box::new(Expression::Literal(1.0)),
-                else_block_expr: Box::new(Expression::Literal(2.0)),
-            }),
+            body: Some(expr(ExpressionKind::Conditional {
+                cond_expr: Box::new(expr(ExpressionKind::Literal(5.0))),
+                if_block_expr: Box::new(expr(ExpressionKind::Literal(1.0))),
+                else_block_expr: Some(Box::new(expr(ExpressionKind::Literal(2.0)))),
+            })),
+            span: Span::default(),
+            id: NodeId::DUMMY,
         })];
 
         let left_tokens = vec![];
         let expected_result = (expected_tree, left_tokens);
 
-        assert_eq!(parse_result, expected_result)
+        assert_spanless_eq!(parse_result, expected_result)
     }
 
     #[test]
     fn parse_expr_conditional_with_leftover_tokens() {
         let input_str = r#"if 5 {1} else {2} fun"#;
         let token_stream = lexer::tokenize(input_str).unwrap();
-        let parse_result = parse(&token_stream, &[]).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
         let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
             prototype: Prototype {
                 name: "".to_string(),
                 args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
             },
-            body: Some(Expression::Conditional {
-                cond_expr: Box::new(Expression::Literal(5.0)),
-                if_block_expr: Box::new(Expression::Literal(1.0)),
-                else_block_expr: Box::new(Expression::Literal(2.0)),
-            }),
+            body: Some(expr(ExpressionKind::Conditional {
+                cond_expr: Box::new(expr(ExpressionKind::Literal(5.0))),
+                if_block_expr: Box::new(expr(ExpressionKind::Literal(1.0))),
+                else_block_expr: Some(Box::new(expr(ExpressionKind::Literal(2.0)))),
+            })),
+            span: Span::default(),
+            id: NodeId::DUMMY,
         })];
 
         let left_tokens = vec![Token::Fun];
         let expected_result = (expected_tree, left_tokens);
 
-        assert_eq!(parse_result, expected_result)
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    #[test]
+    fn parse_expr_conditional_chain() {
+        let input_str = r#"if x { 1 } else if y { 2 } else { 3 } if z { 4 }"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
+        let anonymous = |body| {
+            ASTNode::FunctionNode(Function {
+                is_public: false,
+                prototype: Prototype {
+                    name: "".to_string(),
+                    args: vec![],
+                    kind: PrototypeKind::Function,
+                    span: Span::default(),
+                    id: NodeId::DUMMY,
+                },
+                body: Some(body),
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            })
+        };
+        let expected_tree = vec![
+            anonymous(expr(ExpressionKind::Conditional {
+                cond_expr: Box::new(expr(ExpressionKind::Variable("x".to_string()))),
+                if_block_expr: Box::new(expr(ExpressionKind::Literal(1.0))),
+                else_block_expr: Some(Box::new(expr(ExpressionKind::Conditional {
+                    cond_expr: Box::new(expr(ExpressionKind::Variable("y".to_string()))),
+                    if_block_expr: Box::new(expr(ExpressionKind::Literal(2.0))),
+                    else_block_expr: Some(Box::new(expr(ExpressionKind::Literal(3.0)))),
+                }))),
+            })),
+            anonymous(expr(ExpressionKind::Conditional {
+                cond_expr: Box::new(expr(ExpressionKind::Variable("z".to_string()))),
+                if_block_expr: Box::new(expr(ExpressionKind::Literal(4.0))),
+                else_block_expr: None,
+            })),
+        ];
+
+        let left_tokens = vec![];
+        let expected_result = (expected_tree, left_tokens);
+
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    #[test]
+    fn parse_expr_conditional_else_requires_block_or_if() {
+        let input_str = "if x { 1 } else 2";
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let error = parse(&token_stream, &[]).unwrap_err().errors.remove(0);
+        assert!(matches!(
+            error,
+            ParseError::UnexpectedToken {
+                expected: Expected::OneOf(alternatives),
+                found: Token::Number(_),
+                ..
+            } if alternatives == vec![
+                Expected::Token(Token::If),
+                Expected::Token(Token::OpeningBrace),
+            ]
+        ));
+    }
+
+    #[test]
+    fn parse_error_reports_location() {
+        let input_str = "fun kaya() {\n  5\n";
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = parse(&token_stream, &[]);
+        assert!(parse_result.unwrap().0.is_empty());
+
+        let input_str = "fun kaya() {\n  5 )";
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = parse(&token_stream, &[]);
+        let expected_error = ParseError::UnexpectedToken {
+            expected: Expected::OneOf(vec![
+                Expected::Token(Token::Delimiter),
+                Expected::Token(Token::ClosingBrace),
+            ]),
+            found: Token::ClosingParenthesis,
+            span: Span {
+                file: FileId::default(),
+                start: 17,
+                end: 18,
+                line: 2,
+                column: 5,
+            },
+        };
+        assert_eq!(parse_result.unwrap_err().errors, vec![expected_error]);
+    }
+
+    #[test]
+    fn parse_error_unknown_operator() {
+        let input_str = r#"fun kaya(x) { x % 2 }"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let error = parse(&token_stream, &[]).unwrap_err().errors.remove(0);
+        assert!(matches!(
+            &error,
+            ParseError::UnknownOperator { operator, .. } if operator == "%"
+        ));
+        assert_eq!(error.code(), "P0002");
+        assert_eq!(
+            error.to_string(),
+            "error[P0002] at 1:17: unknown operator `%`"
+        );
+    }
+
+    #[test]
+    fn parse_error_expected_alternatives() {
+        let input_str = r#"use kaya(x 5);"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let error = parse(&token_stream, &[]).unwrap_err().errors.remove(0);
+        assert_eq!(error.code(), "P0001");
+        assert_eq!(
+            error.to_string(),
+            "error[P0001] at 1:12: expected identifier, `,` or `)`, found number `5`"
+        );
+    }
+
+    #[test]
+    fn parse_expr_multi_char_operators() {
+        let input_str = r#"a <= b && c != d || e"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
+        let variable = |name: &str| Box::new(expr(ExpressionKind::Variable(name.to_string())));
+        let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
+            prototype: Prototype {
+                name: "".to_string(),
+                args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            },
+            body: Some(expr(ExpressionKind::Binary(
+                "||".to_string(),
+                Box::new(expr(ExpressionKind::Binary(
+                    "&&".to_string(),
+                    Box::new(expr(ExpressionKind::Binary(
+                        "<=".to_string(),
+                        variable("a"),
+                        variable("b"),
+                    ))),
+                    Box::new(expr(ExpressionKind::Binary(
+                        "!=".to_string(),
+                        variable("c"),
+                        variable("d"),
+                    ))),
+                ))),
+                variable("e"),
+            ))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
+        })];
+
+        let left_tokens = vec![];
+        let expected_result = (expected_tree, left_tokens);
+
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    #[test]
+    fn parse_expr_binary_precedence_climbing() {
+        let input_str = r#"a - b * c - d"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
+        let variable = |name: &str| Box::new(expr(ExpressionKind::Variable(name.to_string())));
+        let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
+            prototype: Prototype {
+                name: "".to_string(),
+                args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            },
+            body: Some(expr(ExpressionKind::Binary(
+                "-".to_string(),
+                Box::new(expr(ExpressionKind::Binary(
+                    "-".to_string(),
+                    variable("a"),
+                    Box::new(expr(ExpressionKind::Binary(
+                        "*".to_string(),
+                        variable("b"),
+                        variable("c"),
+                    ))),
+                ))),
+                variable("d"),
+            ))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
+        })];
+
+        let left_tokens = vec![];
+        let expected_result = (expected_tree, left_tokens);
+
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    #[test]
+    fn parse_recovers_from_multiple_errors() {
+        let input_str = r#"
+            fun first(x) { x % 2 }
+            fun second() { 1 }
+            fun third(x y { x }
+            use fourth();
+            fun fifth() { ) }
+            fun sixth() { 6 }
+        "#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let failure = parse(&token_stream, &[]).unwrap_err();
+        let error_lines: Vec<_> = failure.errors.iter().map(|e| e.span().line).collect();
+        assert_eq!(error_lines, vec![2, 4, 6]);
+
+        let names: Vec<_> = failure
+            .nodes
+            .iter()
+            .map(|node| match node {
+                ASTNode::ExternNode(prototype) => prototype.name.as_str(),
+                ASTNode::FunctionNode(function) => function.prototype.name.as_str(),
+                ASTNode::ImportNode(import) => import.path.as_str(),
+                ASTNode::ModuleNode(module) => module.name.as_str(),
+            })
+            .collect();
+        assert_eq!(names, vec!["second", "fourth", "sixth"]);
+    }
+
+    #[test]
+    fn parse_recovers_at_next_item() {
+        let input_str = r#"fun broken() { 1 fun fine() { 2 }; 3 ) ; 4"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let failure = parse(&token_stream, &[]).unwrap_err();
+        assert_eq!(failure.errors.len(), 2);
+        assert_eq!(failure.nodes.len(), 3);
+        assert_spanless_eq!(
+            failure.nodes[2],
+            ASTNode::FunctionNode(Function {
+                is_public: false,
+                prototype: Prototype {
+                    name: "".to_string(),
+                    args: vec![],
+                    kind: PrototypeKind::Function,
+                    span: Span::default(),
+                    id: NodeId::DUMMY,
+                },
+                body: Some(expr(ExpressionKind::Literal(4.0))),
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_expr_right_associative_assignment() {
+        let input_str = r#"a = b = c / d > e"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
+        let variable = |name: &str| Box::new(expr(ExpressionKind::Variable(name.to_string())));
+        let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
+            prototype: Prototype {
+                name: "".to_string(),
+                args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            },
+            body: Some(expr(ExpressionKind::Binary(
+                "=".to_string(),
+                variable("a"),
+                Box::new(expr(ExpressionKind::Binary(
+                    "=".to_string(),
+                    variable("b"),
+                    Box::new(expr(ExpressionKind::Binary(
+                        ">".to_string(),
+                        Box::new(expr(ExpressionKind::Binary(
+                            "/".to_string(),
+                            variable("c"),
+                            variable("d"),
+                        ))),
+                        variable("e"),
+                    ))),
+                ))),
+            ))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
+        })];
+
+        let left_tokens = vec![];
+        let expected_result = (expected_tree, left_tokens);
+
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    #[test]
+    fn parse_expr_with_custom_settings() {
+        let input_str = r#"a % b % c"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let mut settings = ParserSettings::default();
+        settings.add_binary_operator("%", 40, Associativity::Right);
+        let parse_result = strip_spans(parse_with_settings(&token_stream, &[], &mut settings));
+        let variable = |name: &str| Box::new(expr(ExpressionKind::Variable(name.to_string())));
+        let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
+            prototype: Prototype {
+                name: "".to_string(),
+                args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            },
+            body: Some(expr(ExpressionKind::Binary(
+                "%".to_string(),
+                variable("a"),
+                Box::new(expr(ExpressionKind::Binary(
+                    "%".to_string(),
+                    variable("b"),
+                    variable("c"),
+                ))),
+            ))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
+        })];
+
+        let left_tokens = vec![];
+        let expected_result = (expected_tree, left_tokens);
+
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    #[test]
+    fn parse_user_defined_binary_operator() {
+        let input_str = r#"
+            fun binary: 1 (x, y) { y }
+            a : b + c : d
+        "#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
+        let variable = |name: &str| Box::new(expr(ExpressionKind::Variable(name.to_string())));
+        let expected_tree = vec![
+            ASTNode::FunctionNode(Function {
+                is_public: false,
+                prototype: Prototype {
+                    name: "binary:".to_string(),
+                    args: vec!["x".to_string(), "y".to_string()],
+                    kind: PrototypeKind::BinaryOperator { precedence: 1 },
+                    span: Span::default(),
+                    id: NodeId::DUMMY,
+                },
+                body: Some(expr(ExpressionKind::Variable("y".to_string()))),
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            }),
+            ASTNode::FunctionNode(Function {
+                is_public: false,
+                prototype: Prototype {
+                    name: "".to_string(),
+                    args: vec![],
+                    kind: PrototypeKind::Function,
+                    span: Span::default(),
+                    id: NodeId::DUMMY,
+                },
+                body: Some(expr(ExpressionKind::Binary(
+                    ":".to_string(),
+                    Box::new(expr(ExpressionKind::Binary(
+                        ":".to_string(),
+                        variable("a"),
+                        Box::new(expr(ExpressionKind::Binary(
+                            "+".to_string(),
+                            variable("b"),
+                            variable("c"),
+                        ))),
+                    ))),
+                    variable("d"),
+                ))),
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            }),
+        ];
+
+        let left_tokens = vec![];
+        let expected_result = (expected_tree, left_tokens);
+
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    #[test]
+    fn parse_user_defined_unary_operator() {
+        let input_str = r#"
+            fun unary~ (x) { 0 - x }
+            ~~a * b
+        "#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
+        let expected_tree = vec![
+            ASTNode::FunctionNode(Function {
+                is_public: false,
+                prototype: Prototype {
+                    name: "unary~".to_string(),
+                    args: vec!["x".to_string()],
+                    kind: PrototypeKind::UnaryOperator,
+                    span: Span::default(),
+                    id: NodeId::DUMMY,
+                },
+                body: Some(expr(ExpressionKind::Binary(
+                    "-".to_string(),
+                    Box::new(expr(ExpressionKind::Literal(0.0))),
+                    Box::new(expr(ExpressionKind::Variable("x".to_string()))),
+                ))),
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            }),
+            ASTNode::FunctionNode(Function {
+                is_public: false,
+                prototype: Prototype {
+                    name: "".to_string(),
+                    args: vec![],
+                    kind: PrototypeKind::Function,
+                    span: Span::default(),
+                    id: NodeId::DUMMY,
+                },
+                body: Some(expr(ExpressionKind::Binary(
+                    "*".to_string(),
+                    Box::new(expr(ExpressionKind::Unary(
+                        "~".to_string(),
+                        Box::new(expr(ExpressionKind::Unary(
+                            "~".to_string(),
+                            Box::new(expr(ExpressionKind::Variable("a".to_string()))),
+                        ))),
+                    ))),
+                    Box::new(expr(ExpressionKind::Variable("b".to_string()))),
+                ))),
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            }),
+        ];
+
+        let left_tokens = vec![];
+        let expected_result = (expected_tree, left_tokens);
+
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    #[test]
+    fn parse_user_defined_operator_persists_in_settings() {
+        let mut settings = ParserSettings::default();
+        let token_stream = lexer::tokenize(r#"use binary% 40 (a, b);"#).unwrap();
+        let (tree, _) = parse_with_settings(&token_stream, &[], &mut settings).unwrap();
+
+        let token_stream = lexer::tokenize(r#"a % b"#).unwrap();
+        assert!(parse_with_settings(&token_stream, &tree, &mut settings).is_ok());
+        assert!(parse(&token_stream, &tree).is_err());
+    }
+
+    #[test]
+    fn parse_builtin_operator_redefinitions_fail() {
+        for (input_str, operator) in [
+            ("fun binary+ 50 (a, b) { a }", "+"),
+            ("fun binary= (a, b) { a }", "="),
+            ("use binary&&(a, b);", "&&"),
+            ("fun unary!(v) { v }", "!"),
+            ("fun unary-(v) { v }", "-"),
+        ] {
+            let token_stream = lexer::tokenize(input_str).unwrap();
+            let failure = parse(&token_stream, &[]).unwrap_err();
+            assert_eq!(failure.errors.len(), 1, "{input_str}");
+            assert!(matches!(
+                &failure.errors[0],
+                ParseError::BuiltinOperator { operator: op, .. } if op == operator
+            ));
+        }
+
+        // built-ins keep their precedence and associativity
+        let token_stream = lexer::tokenize("fun binary+ 50 (a, b) { a } 1 + 2 * 3").unwrap();
+        let failure = parse(&token_stream, &[]).unwrap_err();
+        assert_eq!(
+            failure.errors[0].to_string(),
+            "error[P0008] at 1:11: cannot redefine the built-in operator `+`"
+        );
+        assert_eq!(
+            SExpr(&failure.nodes[..]).to_string(),
+            "(fun () (+ 1 (* 2 3)))\n"
+        );
+
+        // `!` is only a built-in prefix operator, `binary!` is free
+        let token_stream = lexer::tokenize("fun binary! (a, b) { a } 1 ! 2").unwrap();
+        assert!(parse(&token_stream, &[]).is_ok());
+    }
+
+    #[test]
+    fn parse_user_defined_operator_errors() {
+        let input_str = r#"
+            fun binary| 0.5 (a, b) { a }
+            fun binary& (a) { a }
+            fun unary^ (a, b) { a }
+            fun binary(a) { a }
+        "#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let failure = parse(&token_stream, &[]).unwrap_err();
+        let codes: Vec<_> = failure.errors.iter().map(ParseError::code).collect();
+        assert_eq!(codes, vec!["P0003", "P0004", "P0004"]);
+        assert_eq!(failure.nodes.len(), 1);
+    }
+
+    #[test]
+    fn parse_expr_builtin_unary_operators() {
+        let input_str = r#"-x * y == !-z"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
+        let variable = |name: &str| Box::new(expr(ExpressionKind::Variable(name.to_string())));
+        let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
+            prototype: Prototype {
+                name: "".to_string(),
+                args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            },
+            body: Some(expr(ExpressionKind::Binary(
+                "==".to_string(),
+                Box::new(expr(ExpressionKind::Binary(
+                    "*".to_string(),
+                    Box::new(expr(ExpressionKind::Unary("-".to_string(), variable("x")))),
+                    variable("y"),
+                ))),
+                Box::new(expr(ExpressionKind::Unary(
+                    "!".to_string(),
+                    Box::new(expr(ExpressionKind::Unary("-".to_string(), variable("z")))),
+                ))),
+            ))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
+        })];
+
+        let left_tokens = vec![];
+        let expected_result = (expected_tree, left_tokens);
+
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    #[test]
+    fn parse_expr_unary_operator_precedence() {
+        let input_str = r#"!a == b"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let mut settings = ParserSettings::default();
+        settings.add_unary_operator("!", 8);
+        let parse_result = strip_spans(parse_with_settings(&token_stream, &[], &mut settings));
+        let variable = |name: &str| Box::new(expr(ExpressionKind::Variable(name.to_string())));
+        let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
+            prototype: Prototype {
+                name: "".to_string(),
+                args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            },
+            body: Some(expr(ExpressionKind::Unary(
+                "!".to_string(),
+                Box::new(expr(ExpressionKind::Binary(
+                    "==".to_string(),
+                    variable("a"),
+                    variable("b"),
+                ))),
+            ))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
+        })];
+
+        let left_tokens = vec![];
+        let expected_result = (expected_tree, left_tokens);
+
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    #[test]
+    fn parse_error_unknown_unary_operator() {
+        for input_str in ["*x", "1 + <x", "-|x"] {
+            let token_stream = lexer::tokenize(input_str).unwrap();
+            let error = parse(&token_stream, &[]).unwrap_err().errors.remove(0);
+            let operator = input_str.rfind(|ch: char| ch != 'x').unwrap();
+            assert!(
+                matches!(
+                    &error,
+                    ParseError::UnexpectedToken {
+                        expected: Expected::Expression,
+                        found: Token::Operator(_),
+                        span,
+                    } if span.start == operator
+                ),
+                "{input_str}: {error:?}"
+            );
+        }
+
+        let token_stream = lexer::tokenize("use unary|(x); -|x").unwrap();
+        assert!(parse(&token_stream, &[]).is_ok());
+    }
+
+    #[test]
+    fn parse_expr_var_in() {
+        let input_str = r#"fun f(x) { var x = x + 1, y in var y = x in y }"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
+        let variable = |name: &str| expr(ExpressionKind::Variable(name.to_string()));
+        let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
+            prototype: Prototype {
+                name: "f".to_string(),
+                args: vec!["x".to_string()],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            },
+            body: Some(expr(ExpressionKind::VarIn {
+                variables: vec![
+                    (
+                        "x".to_string(),
+                        Some(expr(ExpressionKind::Binary(
+                            "+".to_string(),
+                            Box::new(variable("x")),
+                            Box::new(expr(ExpressionKind::Literal(1.0))),
+                        ))),
+                    ),
+                    ("y".to_string(), None),
+                ],
+                body: Box::new(expr(ExpressionKind::VarIn {
+                    variables: vec![("y".to_string(), Some(variable("x")))],
+                    body: Box::new(variable("y")),
+                })),
+            })),
+            span: Span::default(),
+            id: NodeId::DUMMY,
+        })];
+
+        let left_tokens = vec![];
+        let expected_result = (expected_tree, left_tokens);
+
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    #[test]
+    fn parse_expr_var_in_requires_in() {
+        let input_str = r#"var x = 1 x"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let error = parse(&token_stream, &[]).unwrap_err().errors.remove(0);
+        assert_eq!(
+            error,
+            ParseError::UnexpectedToken {
+                expected: Expected::OneOf(vec![
+                    Expected::Token(Token::Comma),
+                    Expected::Token(Token::In)
+                ]),
+                found: Token::Ident("x".into()),
+                span: Span {
+                    file: FileId::default(),
+                    start: 10,
+                    end: 11,
+                    line: 1,
+                    column: 11,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn parse_expr_for_loop() {
+        let input_str = r#"for i = 0, i < n, 2 { printd(i) } for j = 1, j { j }"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
+        let variable = |name: &str| Box::new(expr(ExpressionKind::Variable(name.to_string())));
+        let anonymous = |body| {
+            ASTNode::FunctionNode(Function {
+                is_public: false,
+                prototype: Prototype {
+                    name: "".to_string(),
+                    args: vec![],
+                    kind: PrototypeKind::Function,
+                    span: Span::default(),
+                    id: NodeId::DUMMY,
+                },
+                body: Some(body),
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            })
+        };
+        let expected_tree = vec![
+            anonymous(expr(ExpressionKind::For {
+                var_name: "i".to_string(),
+                start: Box::new(expr(ExpressionKind::Literal(0.0))),
+                cond: Box::new(expr(ExpressionKind::Binary(
+                    "<".to_string(),
+                    variable("i"),
+                    variable("n"),
+                ))),
+                step: Some(Box::new(expr(ExpressionKind::Literal(2.0)))),
+                body: Box::new(expr(ExpressionKind::Call(
+                    "printd".into(),
+                    vec![expr(ExpressionKind::Variable("i".to_string()))],
+                ))),
+            })),
+            anonymous(expr(ExpressionKind::For {
+                var_name: "j".to_string(),
+                start: Box::new(expr(ExpressionKind::Literal(1.0))),
+                cond: variable("j"),
+                step: None,
+                body: variable("j"),
+            })),
+        ];
+
+        let left_tokens = vec![];
+        let expected_result = (expected_tree, left_tokens);
+
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    #[test]
+    fn parse_expr_while_loop() {
+        let input_str = r#"while x > 0 { x = x - 1 }"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
+        let variable = |name: &str| Box::new(expr(ExpressionKind::Variable(name.to_string())));
+        let expected_tree = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
+            prototype: Prototype {
+                name: "".to_string(),
+                args: vec![],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            },
+            body: Some(expr(ExpressionKind::While {
+                cond: Box::new(expr(ExpressionKind::Binary(
+                    ">".to_string(),
+                    variable("x"),
+                    Box::new(expr(ExpressionKind::Literal(0.0))),
+                ))),
+                body: Box::new(expr(ExpressionKind::Binary(
+                    "=".to_string(),
+                    variable("x"),
+                    Box::new(expr(ExpressionKind::Binary(
+                        "-".to_string(),
+                        variable("x"),
+                        Box::new(expr(ExpressionKind::Literal(1.0))),
+                    ))),
+                ))),
+            })),
+            span: Span::default(),
+            id: NodeId::DUMMY,
+        })];
+
+        let left_tokens = vec![];
+        let expected_result = (expected_tree, left_tokens);
+
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    #[test]
+    fn parse_expr_for_loop_requires_assignment() {
+        let input_str = r#"for i < 0, i { i }"#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let error = parse(&token_stream, &[]).unwrap_err().errors.remove(0);
+        assert!(matches!(
+            error,
+            ParseError::UnexpectedToken {
+                found: Token::Operator(op),
+                ..
+            } if op == "<"
+        ));
+    }
+
+    #[test]
+    fn parse_block_expressions() {
+        let input_str = r#"
+            fun f(x) { printd(x); x = x + 1; x }
+            fun g(x) { if x { printd(x); 1 } else { } }
+            fun h() { 1; }
+        "#;
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let parse_result = strip_spans(parse(&token_stream, &[]));
+        let variable = |name: &str| expr(ExpressionKind::Variable(name.to_string()));
+        let function = |name: &str, args: Vec<&str>, body| {
+            ASTNode::FunctionNode(Function {
+                is_public: false,
+                prototype: Prototype {
+                    name: name.to_string(),
+                    args: args.into_iter().map(str::to_string).collect(),
+                    kind: PrototypeKind::Function,
+                    span: Span::default(),
+                    id: NodeId::DUMMY,
+                },
+                body: Some(body),
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            })
+        };
+        let expected_tree = vec![
+            function(
+                "f",
+                vec!["x"],
+                expr(ExpressionKind::Block(vec![
+                    expr(ExpressionKind::Call("printd".into(), vec![variable("x")])),
+                    expr(ExpressionKind::Binary(
+                        "=".to_string(),
+                        Box::new(variable("x")),
+                        Box::new(expr(ExpressionKind::Binary(
+                            "+".to_string(),
+                            Box::new(variable("x")),
+                            Box::new(expr(ExpressionKind::Literal(1.0))),
+                        ))),
+                    )),
+                    variable("x"),
+                ])),
+            ),
+            function(
+                "g",
+                vec!["x"],
+                expr(ExpressionKind::Conditional {
+                    cond_expr: Box::new(variable("x")),
+                    if_block_expr: Box::new(expr(ExpressionKind::Block(vec![
+                        expr(ExpressionKind::Call("printd".into(), vec![variable("x")])),
+                        expr(ExpressionKind::Literal(1.0)),
+                    ]))),
+                    else_block_expr: Some(Box::new(expr(ExpressionKind::Block(vec![])))),
+                }),
+            ),
+            function("h", vec![], expr(ExpressionKind::Literal(1.0))),
+        ];
+
+        let left_tokens = vec![];
+        let expected_result = (expected_tree, left_tokens);
+
+        assert_spanless_eq!(parse_result, expected_result)
+    }
+
+    /// `1 + 1 + ... + 1` with `terms` terms.
+    fn addition_chain(terms: usize) -> String {
+        vec!["1"; terms].join(" + ")
+    }
+
+    #[test]
+    fn parse_long_operator_chain() {
+        // a chain doesn't nest, however long it is
+        let input_str = addition_chain(5_000);
+        let token_stream = lexer::tokenize(&input_str).unwrap();
+        let (mut tree, _) = parse(&token_stream, &[]).unwrap();
+        let Some(ASTNode::FunctionNode(Function {
+            body: Some(mut expr),
+            ..
+        })) = tree.pop()
+        else {
+            panic!("expected an anonymous function");
+        };
+
+        // taken apart by hand, dropping the tree would recurse 5000 levels deep
+        let mut additions = 0;
+        while let Expression {
+            kind: ExpressionKind::Binary(op, lhs, _),
+            ..
+        } = expr
+        {
+            assert_eq!(op, "+");
+            additions += 1;
+            expr = *lhs;
+        }
+        assert_eq!(additions, 4_999);
+    }
+
+    /// How often the parser looks at a token to parse `input_str`.
+    fn token_visits(input_str: &str, settings: &mut ParserSettings) -> usize {
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let mut parser = Parser::new(&token_stream);
+        let mut errors = Vec::new();
+        parse_items(&mut parser, settings, &mut Vec::new(), &mut errors);
+        assert_eq!(errors, vec![]);
+        parser.token_visits()
+    }
+
+    #[test]
+    fn parse_is_linear_in_chain_length() {
+        let mut settings = ParserSettings::default();
+        let short = token_visits(&addition_chain(250), &mut settings);
+        let long = token_visits(&addition_chain(2_000), &mut settings);
+        // 8 times the input, a quadratic parser would look 64 times as often
+        assert!(
+            long <= short * 8,
+            "{short} visits for 250 terms, {long} for 2000"
+        );
+    }
+
+    #[test]
+    fn parse_deep_nesting_is_rejected() {
+        let parens = format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000));
+        let prefix_operators = format!("{}1", "-".repeat(10_000));
+        let assignments = format!("{}1", "a = ".repeat(10_000));
+        for input_str in [parens, prefix_operators, assignments] {
+            let token_stream = lexer::tokenize(&input_str).unwrap();
+            let error = parse(&token_stream, &[]).unwrap_err().errors.remove(0);
+            assert!(matches!(
+                error,
+                ParseError::NestingTooDeep { limit: 256, .. }
+            ));
+        }
+    }
+
+    #[test]
+    fn parse_nesting_limit_is_configurable() {
+        let mut settings = ParserSettings {
+            max_nesting_depth: 2,
+            ..ParserSettings::default()
+        };
+
+        let token_stream = lexer::tokenize("((1)) -(1) --1").unwrap();
+        assert!(parse_with_settings(&token_stream, &[], &mut settings).is_ok());
+
+        let token_stream = lexer::tokenize("(((1)))").unwrap();
+        let error = parse_with_settings(&token_stream, &[], &mut settings)
+            .unwrap_err()
+            .errors
+            .remove(0);
+        assert_eq!(error.code(), "P0005");
+        assert_eq!(error.span().column, 4);
+    }
+
+    #[test]
+    fn parse_leaves_incomplete_item_in_place() {
+        let input_str = "fun f() { 1 } fun g() { 2 +";
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let (tree, left_tokens) = parse(&token_stream, &[]).unwrap();
+
+        assert_eq!(tree.len(), 1);
+        assert!(std::ptr::eq(left_tokens, &token_stream[7..]));
+    }
+
+    #[test]
+    fn session_buffers_incomplete_items() {
+        let mut session = ParseSession::default();
+
+        assert_eq!(session.push_str("fun f(x) {\n"), Ok(vec![]));
+        assert!(session.needs_more_input());
+
+        let nodes = session.push_str("  x\n}\n1 +").unwrap();
+        let expected = vec![ASTNode::FunctionNode(Function {
+            is_public: false,
+            prototype: Prototype {
+                name: "f".to_string(),
+                args: vec!["x".to_string()],
+                kind: PrototypeKind::Function,
+                span: Span::default(),
+                id: NodeId::DUMMY,
+            },
+            body: Some(expr(ExpressionKind::Variable("x".to_string()))),
+            span: Span::default(),
+            id: NodeId::DUMMY,
+        })];
+        assert_spanless_eq!(nodes, expected);
+        assert!(session.needs_more_input());
+
+        let nodes = session.push_str(" 2").unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert!(!session.needs_more_input());
+    }
+
+    #[test]
+    fn session_keeps_operators_and_positions() {
+        let mut session = ParseSession::default();
+        session.push_str("fun binary% 50 (a, b) { a }\n").unwrap();
+        session.push_str("1 % 2\n").unwrap();
+        assert!(session.settings().binary_operator("%").is_some());
+
+        let error = session
+            .push_str("fun kaya() {\n  5 )")
+            .unwrap_err()
+            .errors
+            .remove(0);
+        assert_eq!(error.span().start, 51);
+        assert_eq!((error.span().line, error.span().column), (4, 5));
+    }
+
+    #[test]
+    fn session_rejects_items_with_lex_errors() {
+        let mut session = ParseSession::default();
+
+        let failure = session.push_str("fun f() {\n  §").unwrap_err();
+        assert_eq!(failure.errors.len(), 1);
+        assert_eq!(failure.errors[0].code(), "P0006");
+        assert!(session.needs_more_input());
+
+        // the item is complete, but its bad text is still in it
+        let failure = session.push_str(" 1 }").unwrap_err();
+        assert_eq!(failure.errors.len(), 1);
+        assert_eq!(failure.errors[0].span().line, 2);
+        assert_eq!(failure.nodes, vec![]);
+        assert!(!session.needs_more_input());
+
+        let failure = session
+            .push_str("fun g() { 1 § } fun h() { 2 }")
+            .unwrap_err();
+        assert!(matches!(
+            &failure.nodes[..],
+            [ASTNode::FunctionNode(h)] if h.prototype.name == "h"
+        ));
+    }
+
+    const SYNTAX_SAMPLE: &str = r#"
+        # externals
+        use printd(x);
+
+        fun binary| 5 (a, b) { if a { 1 } else if b { 1 } else { 0 } }
+
+        fun count(n) {
+            var i = 0, total in       # running sum
+            for i = 1, i < n, 2 {
+                total = total + -i * (i | !n);
+                printd(total)
+            };
+            while 0 { }
+        }
+
+        count(10)
+    "#;
+
+    #[test]
+    fn syntax_tree_is_lossless() {
+        for input_str in [
+            SYNTAX_SAMPLE,
+            "",
+            "  # only a comment",
+            "fun f( { 1 }\n§ 2 +",
+        ] {
+            let tree = parse_syntax(input_str);
+            assert_eq!(tree.root.to_string(), input_str);
+            assert_eq!(tree.root.text_range(), 0..input_str.len());
+        }
+    }
+
+    #[test]
+    fn syntax_tree_lowers_to_ast() {
+        let tree = parse_syntax(SYNTAX_SAMPLE);
+        assert_eq!(tree.errors, vec![]);
+
+        let token_stream = lexer::tokenize(SYNTAX_SAMPLE).unwrap();
+        let (expected_tree, _) = parse(&token_stream, &[]).unwrap();
+        let lowered = tree.lower();
+        assert_eq!(lowered.nodes, expected_tree);
+        assert_eq!(lowered.dropped, 0);
+    }
+
+    #[test]
+    fn syntax_tree_lowers_with_settings() {
+        let file = FileId(3);
+        let mut settings = ParserSettings {
+            next_node_id: NodeId::default().next().next(),
+            ..ParserSettings::default()
+        };
+        let tree = parse_syntax_file(file, SYNTAX_SAMPLE, &mut settings.clone());
+
+        let token_stream = lexer::tokenize_file(file, SYNTAX_SAMPLE).unwrap();
+        let (expected_tree, _) = parse_with_settings(&token_stream, &[], &mut settings).unwrap();
+        assert_eq!(tree.lower().nodes, expected_tree);
+    }
+
+    /// Prints the items parsed from `input_str`, checking that the printed
+    /// source parses back to them and prints the same again.
+    fn print_round_trip(input_str: &str, settings: &ParserSettings) -> String {
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let (tree, _) = parse_with_settings(&token_stream, &[], &mut settings.clone()).unwrap();
+        let printed = settings.printer().print_items(&tree);
+
+        let token_stream = lexer::tokenize(&printed).unwrap();
+        let (reparsed, left_tokens) =
+            parse_with_settings(&token_stream, &[], &mut settings.clone()).unwrap();
+        assert!(left_tokens.is_empty());
+        assert_spanless_eq!(reparsed, tree);
+        assert_eq!(settings.printer().print_items(&reparsed), printed);
+        printed
+    }
+
+    #[test]
+    fn print_round_trips() {
+        let printed = print_round_trip(SYNTAX_SAMPLE, &ParserSettings::default());
+        let expected = r#"use printd(x);
+
+fun binary| 5 (a, b) {
+    if a { 1 } else if b { 1 } else { 0 }
+}
+
+fun count(n) {
+    var i = 0, total in for i = 1, i < n, 2 {
+        total = total + -i * (i | !n);
+        printd(total)
+    };
+    while 0 {}
+}
+
+count(10);
+"#;
+        assert_eq!(printed, expected);
+
+        let printed = print_round_trip(
+            r#"import "a.kl" import "b.kl"
+            mod m { pub fun f(x) { x } mod n { pub fun g(x) { x } fun h() { 0 } } }
+            m::f(1) m::n::g(2)"#,
+            &ParserSettings::default(),
+        );
+        let expected = r#"import "a.kl";
+import "b.kl";
+
+mod m {
+    pub fun f(x) {
+        x
+    }
+
+    mod n {
+        pub fun g(x) {
+            x
+        }
+
+        fun h() {
+            0
+        }
+    }
+}
+
+m::f(1);
+m::n::g(2);
+"#;
+        assert_eq!(printed, expected);
+    }
+
+    #[test]
+    fn print_minimal_parentheses() {
+        let mut settings = ParserSettings::default();
+        settings.add_binary_operator("%", 40, Associativity::Right);
+        let cases = [
+            ("(a + b) * c", "(a + b) * c"),
+            ("a + (b * c)", "a + b * c"),
+            ("(a - b) - c", "a - b - c"),
+            ("a - (b - c)", "a - (b - c)"),
+            ("a = (b = c)", "a = b = c"),
+            ("(a = b) = c", "(a = b) = c"),
+            ("(a % b) % c", "(a % b) % c"),
+            ("a % (b % c)", "a % b % c"),
+            ("(a * b) % c", "(a * b) % c"),
+            ("a * (b % c)", "a * b % c"),
+            ("(-a) * b", "-a * b"),
+            ("-(a * b)", "-(a * b)"),
+            ("-(-a)", "- -a"),
+            ("!(a == b)", "!(a == b)"),
+            ("f((a + b), (var x = 1 in x))", "f(a + b, var x = 1 in x)"),
+            ("(var x = 1 in x) + 1", "(var x = 1 in x) + 1"),
+            ("a + (var x = 1 in x)", "a + var x = 1 in x"),
+            ("(if a { b } else { c }) + 1", "if a { b } else { c } + 1"),
+        ];
+        for (input_str, expected) in cases {
+            assert_eq!(
+                print_round_trip(input_str, &settings),
+                format!("{expected};\n")
+            );
+        }
+
+        // a prefix operator binds looser than user-defined operators above it
+        let printed = print_round_trip(
+            "fun binary^ 60 (a, b) { a } (-a) ^ b; -(a ^ b); -(a * b) ^ c",
+            &ParserSettings::default(),
+        );
+        assert!(printed.ends_with("(-a) ^ b;\n-a ^ b;\n-(a * b) ^ c;\n"));
+    }
+
+    /// Formats `input_str`, checking that formatting keeps its meaning and
+    /// that formatted source stays as it is.
+    fn format_checked(input_str: &str) -> String {
+        let formatted = format_source(input_str).unwrap();
+        assert_spanless_eq!(
+            parse_syntax(&formatted).lower().nodes,
+            parse_syntax(input_str).lower().nodes
+        );
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+        formatted
+    }
+
+    #[test]
+    fn format_keeps_comments() {
+        let input_str = r#"
+# math helpers
+import   "lib.kl"
+use sin( x ) ; use cos(x)   # from libm
+fun  binary% 40(a,b){a-b*(a/b)}
+mod geo{
+  # squares
+  pub fun sq(x){x*x}
+
+
+  fun   cube(x) {  # the third power
+    var y=x*x in
+      y*x }
+}
+fun count(n) { for i=1,i<n {printd(i);
+  # nothing yet
+  }; while !- n{}; -(-n)}
+geo::sq(2)  geo::sq(3)
+# done
+"#;
+        let expected = r#"# math helpers
+import "lib.kl";
+
+use sin(x);
+use cos(x); # from libm
+
+fun binary% 40 (a, b) {
+    a - b * (a / b)
+}
+
+mod geo {
+    # squares
+    pub fun sq(x) {
+        x * x
+    }
+
+    fun cube(x) { # the third power
+        var y = x * x in y * x
+    }
+}
+
+fun count(n) {
+    for i = 1, i < n {
+        printd(i)
+        # nothing yet
+    };
+    while ! -n {};
+    -(-n)
+}
+
+geo::sq(2);
+geo::sq(3);
+
+# done
+"#;
+        assert_eq!(format_checked(input_str), expected);
+    }
+
+    #[test]
+    fn format_sample() {
+        let formatted = format_checked(SYNTAX_SAMPLE);
+        assert!(formatted.starts_with("# externals\nuse printd(x);\n\nfun binary| 5 (a, b) {\n"));
+        assert!(formatted.contains("    var i = 0, total in # running sum\n    for i = 1,"));
+        assert!(format_source("fun f(x) { x + }").is_err());
+    }
+
+    #[test]
+    fn format_indents_lists_broken_by_comments() {
+        let input_str = "fun f(a, # first\nb) { g(a, # twice\nb) }\n\
+                         var x = 1, # one\ny = 2 in x + y";
+        let expected = r#"fun f(a, # first
+    b) {
+    g(a, # twice
+        b)
+}
+
+var x = 1, # one
+    y = 2 in x + y;
+"#;
+        assert_eq!(format_checked(input_str), expected);
+    }
+
+    #[test]
+    fn format_keeps_imported_operators() {
+        // `|` and `~` are defined in `op.kl`, which the formatter doesn't read
+        let input_str = "import \"op.kl\"\nfun main() { 1|~2 }";
+        let expected = "import \"op.kl\";\n\nfun main() {\n    1 | ~2\n}\n";
+        let formatted = format_source(input_str).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+
+        // operators that are known keep being checked
+        assert!(format_source("fun main() { * 2 }").is_err());
+    }
+
+    #[test]
+    fn ast_serializes() {
+        let ast = parse_syntax(SYNTAX_SAMPLE).lower().nodes;
+        let json = serde_json::to_string(&ast).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<ASTNode>>(&json).unwrap(), ast);
+
+        let tree = parse_syntax(
+            "use sin(x); pub fun binary| 5 (a, b) { if a { 1 } else { b } } \
+             mod m { pub fun f(x) { x } } var a = 1, b in -a * (b + m::f(a))",
+        );
+        assert!(tree.errors.is_empty(), "{:?}", tree.errors);
+        let ast = tree.lower().nodes;
+        let expected = r#"(extern sin (x))
+(pub fun binary| 5 (a b) (if a 1 b))
+(mod m (pub fun f (x) x))
+(fun () (var ((a 1) (b)) (* (- a) (+ b (call m::f a)))))
+"#;
+        assert_eq!(SExpr(&ast[..]).to_string(), expected);
+
+        // other frontends can leave spans and ids out
+        let json = r#"[{"ExternNode": {"name": "sin", "args": ["x"], "kind": "Function"}}]"#;
+        let ast: Vec<ASTNode> = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            &ast[..],
+            [ASTNode::ExternNode(Prototype { name, span, id, .. })]
+                if name == "sin" && *span == Span::default() && *id == NodeId::DUMMY
+        ));
+    }
+
+    #[test]
+    fn visitors_walk_every_node() {
+        fn parse_str(input_str: &str) -> Vec<ASTNode> {
+            let token_stream = lexer::tokenize(input_str).unwrap();
+            parse(&token_stream, &[]).unwrap().0
+        }
+
+        #[derive(Default)]
+        struct Calls(Vec<String>);
+
+        impl Visitor for Calls {
+            fn visit_expr(&mut self, expr: &Expression) {
+                if let ExpressionKind::Call(path, _) = &expr.kind {
+                    self.0.push(path.to_string());
+                }
+                walk_expr(self, expr)
+            }
+        }
+
+        struct Rename;
+
+        impl VisitorMut for Rename {
+            fn visit_prototype_mut(&mut self, prototype: &mut Prototype) {
+                for arg in &mut prototype.args {
+                    *arg = arg.to_uppercase();
+                }
+            }
+
+            fn visit_expr_mut(&mut self, expr: &mut Expression) {
+                if let ExpressionKind::Variable(name) = &mut expr.kind {
+                    *name = name.to_uppercase();
+                }
+                walk_expr_mut(self, expr)
+            }
+        }
+
+        struct Double;
+
+        impl Fold for Double {
+            fn fold_expr(&mut self, expr: Expression) -> Expression {
+                match expr.kind {
+                    ExpressionKind::Literal(nb) => Expression {
+                        kind: ExpressionKind::Literal(nb * 2.0),
+                        ..expr
+                    },
+                    _ => fold::walk_expr(self, expr),
+                }
+            }
+        }
+
+        let tree = parse_str(
+            r#"
+            mod m { fun f(x) { var y = g(x) in if y { h(1) } else { m::f(y) } } }
+            for i = 1, i < 2 { k(i) }
+            "#,
+        );
+
+        let mut calls = Calls::default();
+        for node in &tree {
+            calls.visit_node(node);
+        }
+        assert_eq!(calls.0, vec!["g", "h", "m::f", "k"]);
+
+        let mut renamed = tree.clone();
+        for node in &mut renamed {
+            Rename.visit_node_mut(node);
+        }
+        let expected = parse_str(
+            r#"
+            mod m { fun f(X) { var y = g(X) in if Y { h(1) } else { m::f(Y) } } }
+            for i = 1, I < 2 { k(I) }
+            "#,
+        );
+        assert_spanless_eq!(renamed, expected);
+
+        let doubled: Vec<_> = tree
+            .into_iter()
+            .map(|node| Double.fold_node(node))
+            .collect();
+        let expected = parse_str(
+            r#"
+            mod m { fun f(x) { var y = g(x) in if y { h(2) } else { m::f(y) } } }
+            for i = 2, i < 4 { k(i) }
+            "#,
+        );
+        assert_spanless_eq!(doubled, expected);
+    }
+
+    #[test]
+    fn parse_sets_spans_and_ids() {
+        let input_str = "fun f(x) {\n  x + 1\n}";
+        let token_stream = lexer::tokenize(input_str).unwrap();
+        let mut settings = ParserSettings::default();
+        let (tree, _) = parse_with_settings(&token_stream, &[], &mut settings).unwrap();
+        let [ASTNode::FunctionNode(function)] = &tree[..] else {
+            panic!("expected a function");
+        };
+        assert_eq!(function.span.start..function.span.end, 0..input_str.len());
+        assert_eq!(
+            &input_str[function.prototype.span.start..function.prototype.span.end],
+            "f(x)"
+        );
+
+        let body = function.body.as_ref().unwrap();
+        assert_eq!(&input_str[body.span.start..body.span.end], "x + 1");
+        assert_eq!((body.span.line, body.span.column), (2, 3));
+
+        let ExpressionKind::Binary(_, lhs, rhs) = &body.kind else {
+            panic!("expected a binary expression");
+        };
+        let mut ids = vec![lhs.id, rhs.id, body.id, function.prototype.id, function.id];
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 5);
+
+        // parsing on with the same settings continues the numbering
+        let token_stream = lexer::tokenize("f(2)").unwrap();
+        let (tree, _) = parse_with_settings(&token_stream, &[], &mut settings).unwrap();
+        assert!(tree[0].id() > function.id);
+    }
+
+    #[test]
+    fn syntax_tree_structure() {
+        let tree = parse_syntax("fun f(x) {\n  # add\n  x + 2 * 3\n}");
+        let function = tree.root.children().next().unwrap();
+        assert_eq!(function.kind(), SyntaxKind::Function);
+
+        let body = function.children().nth(1).unwrap();
+        assert_eq!(body.kind(), SyntaxKind::BlockExpr);
+        let body_elements: Vec<_> = body
+            .children_with_tokens()
+            .map(|element| match element {
+                SyntaxElement::Node(node) => node.kind(),
+                SyntaxElement::Token(token) => token.kind(),
+            })
+            .collect();
+        assert_eq!(
+            body_elements,
+            vec![
+                SyntaxKind::OpeningBrace,
+                SyntaxKind::Whitespace,
+                SyntaxKind::Comment,
+                SyntaxKind::Whitespace,
+                SyntaxKind::BinaryExpr,
+                SyntaxKind::Whitespace,
+                SyntaxKind::ClosingBrace,
+            ]
+        );
+
+        let sum = body.children().next().unwrap();
+        assert_eq!(sum.to_string(), "x + 2 * 3");
+        assert_eq!(sum.text_range(), 21..30);
+        let operands: Vec<_> = sum.children().map(|node| node.to_string()).collect();
+        assert_eq!(operands, vec!["x", "2 * 3"]);
+        assert_eq!(sum.parent().unwrap().kind(), SyntaxKind::BlockExpr);
+    }
+
+    #[test]
+    fn syntax_tree_recovers_from_errors() {
+        let tree = parse_syntax("fun f( { 1 }\nfun g() { 2 }\n§ 3 +");
+        let kinds: Vec<_> = tree.root.children().map(|node| node.kind()).collect();
+        assert_eq!(
+            kinds,
+            vec![SyntaxKind::Error, SyntaxKind::Function, SyntaxKind::Error]
+        );
+
+        let codes: Vec<_> = tree.errors.iter().map(|error| error.code()).collect();
+        assert_eq!(codes, vec!["P0001", "P0006", "P0007"]);
+        let lowered = tree.lower();
+        assert_eq!(lowered.nodes.len(), 1);
+        assert_eq!(lowered.dropped, 2);
     }
 }
@@ -0,0 +1,297 @@
+use std::ops::Range;
+
+use crate::{
+    prototype::DEFAULT_OPERATOR_PRECEDENCE,
+    syntax::{Lowered, SyntaxKind, SyntaxNode, SyntaxToken},
+};
+use klang_ast::{
+    expr::{Expression, ExpressionKind},
+    function::{Function, Prototype, PrototypeKind},
+    id::NodeId,
+    node::{ASTNode, Import, Module},
+    path::Path,
+    span::{FileId, Span},
+};
+
+/// Lowers the items of a syntax tree `root` parsed from `file` to AST nodes,
+/// numbering them from `first_id`.
+///
+/// Error nodes and items missing required parts are left out and counted.
+pub(crate) fn lower_root(root: &SyntaxNode, file: FileId, first_id: NodeId) -> Lowered {
+    let mut lowering = Lowering::new(root.to_string(), file, first_id);
+    let nodes = lowering.lower_items(root);
+    Lowered {
+        nodes,
+        dropped: lowering.dropped,
+    }
+}
+
+/// Gives the lowered nodes their spans and ids.
+struct Lowering {
+    source: String,
+    /// Byte offsets at which the lines of `source` start.
+    line_starts: Vec<usize>,
+    file: FileId,
+    next_id: NodeId,
+    /// Items left out so far.
+    dropped: usize,
+}
+
+impl Lowering {
+    fn new(source: String, file: FileId, first_id: NodeId) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+        Self {
+            source,
+            line_starts,
+            file,
+            next_id: first_id,
+            dropped: 0,
+        }
+    }
+
+    /// Span of the source text at `range`.
+    fn span(&self, range: Range<usize>) -> Span {
+        let line = self
+            .line_starts
+            .partition_point(|&start| start <= range.start);
+        let line_start = self.line_starts[line - 1];
+        let column = self.source[line_start..range.start].chars().count() + 1;
+        Span {
+            file: self.file,
+            start: range.start,
+            end: range.end,
+            line: line as u32,
+            column: column as u32,
+        }
+    }
+
+    fn new_id(&mut self) -> NodeId {
+        let id = self.next_id;
+        self.next_id = id.next();
+        id
+    }
+
+    /// Lowers the items below `parent`. Like the parser, gives back the ids
+    /// taken by an item that is left out.
+    fn lower_items(&mut self, parent: &SyntaxNode) -> Vec<ASTNode> {
+        let mut items = Vec::new();
+        for item in parent.children() {
+            let first_id = self.next_id;
+            match self.lower_item(&item) {
+                Some(item) => items.push(item),
+                None => {
+                    self.next_id = first_id;
+                    self.dropped += 1;
+                }
+            }
+        }
+        items
+    }
+
+    fn lower_item(&mut self, item: &SyntaxNode) -> Option<ASTNode> {
+        match item.kind() {
+            SyntaxKind::Function => {
+                let prototype = self.lower_prototype(&child(item, SyntaxKind::Prototype)?)?;
+                let body = self.lower_expr(&child(item, SyntaxKind::BlockExpr)?)?;
+                Some(ASTNode::FunctionNode(Function {
+                    is_public: token(item, SyntaxKind::Pub).is_some(),
+                    prototype,
+                    body: Some(body),
+                    span: self.span(item.text_range()),
+                    id: self.new_id(),
+                }))
+            }
+            SyntaxKind::Extern => {
+                let mut prototype = self.lower_prototype(&child(item, SyntaxKind::Prototype)?)?;
+                // the extern spans its `use` keyword too
+                prototype.span = self.span(item.text_range());
+                Some(ASTNode::ExternNode(prototype))
+            }
+            SyntaxKind::ImportDecl => {
+                let path = token(item, SyntaxKind::String)?;
+                let quoted = path.text();
+                Some(ASTNode::ImportNode(Import {
+                    path: quoted[1..quoted.len() - 1].to_string(),
+                    span: self.span(item.text_range()),
+                    id: self.new_id(),
+                }))
+            }
+            SyntaxKind::Module => {
+                let name = token(item, SyntaxKind::Ident)?.text().to_string();
+                let items = self.lower_items(item);
+                Some(ASTNode::ModuleNode(Module {
+                    name,
+                    items,
+                    span: self.span(item.text_range()),
+                    id: self.new_id(),
+                }))
+            }
+            kind if kind.is_expr() => {
+                let body = self.lower_expr(item)?;
+                let prototype = Prototype {
+                    name: "".to_string(),
+                    args: vec![],
+                    kind: PrototypeKind::Function,
+                    span: body.span,
+                    id: self.new_id(),
+                };
+                Some(ASTNode::FunctionNode(Function {
+                    is_public: false,
+                    prototype,
+                    span: body.span,
+                    body: Some(body),
+                    id: self.new_id(),
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    fn lower_prototype(&mut self, prototype: &SyntaxNode) -> Option<Prototype> {
+        let mut name = token(prototype, SyntaxKind::Ident)?.text().to_string();
+
+        let mut kind = PrototypeKind::Function;
+        if let Some(operator) = token(prototype, SyntaxKind::Operator) {
+            kind = match name.as_str() {
+                "unary" => PrototypeKind::UnaryOperator,
+                "binary" => {
+                    let precedence = match token(prototype, SyntaxKind::Number) {
+                        Some(number) => number.text().parse::<f64>().ok()? as i32,
+                        None => DEFAULT_OPERATOR_PRECEDENCE,
+                    };
+                    PrototypeKind::BinaryOperator { precedence }
+                }
+                _ => return None,
+            };
+            name.push_str(operator.text());
+        }
+
+        let args = child(prototype, SyntaxKind::ParameterList)?
+            .child_tokens()
+            .filter(|token| token.kind() == SyntaxKind::Ident)
+            .map(|token| token.text().to_string())
+            .collect();
+
+        Some(Prototype {
+            name,
+            args,
+            kind,
+            span: self.span(prototype.text_range()),
+            id: self.new_id(),
+        })
+    }
+
+    fn lower_expr(&mut self, expr: &SyntaxNode) -> Option<Expression> {
+        let operands = |lowering: &mut Self| -> Option<Vec<Expression>> {
+            expr.children()
+                .filter(|node| node.kind().is_expr())
+                .map(|node| lowering.lower_expr(&node))
+                .collect()
+        };
+
+        let kind = match expr.kind() {
+            SyntaxKind::LiteralExpr => {
+                ExpressionKind::Literal(token(expr, SyntaxKind::Number)?.text().parse().ok()?)
+            }
+            SyntaxKind::VariableExpr => {
+                ExpressionKind::Variable(token(expr, SyntaxKind::Ident)?.text().to_string())
+            }
+            SyntaxKind::CallExpr => {
+                let segments = expr
+                    .child_tokens()
+                    .filter(|token| token.kind() == SyntaxKind::Ident)
+                    .map(|token| token.text().to_string())
+                    .collect();
+                let args = child(expr, SyntaxKind::ArgumentList)?
+                    .children()
+                    .map(|arg| self.lower_expr(&arg))
+                    .collect::<Option<_>>()?;
+                ExpressionKind::Call(Path { segments }, args)
+            }
+            SyntaxKind::ParenExpr => return operands(self)?.pop(),
+            SyntaxKind::UnaryExpr => {
+                let op = token(expr, SyntaxKind::Operator)?.text().to_string();
+                let [operand]: [Expression; 1] = operands(self)?.try_into().ok()?;
+                ExpressionKind::Unary(op, Box::new(operand))
+            }
+            SyntaxKind::BinaryExpr => {
+                let op = token(expr, SyntaxKind::Operator)?.text().to_string();
+                let [lhs, rhs]: [Expression; 2] = operands(self)?.try_into().ok()?;
+                ExpressionKind::Binary(op, Box::new(lhs), Box::new(rhs))
+            }
+            // like the parser, a block of a single expression is that expression
+            SyntaxKind::BlockExpr => match operands(self)? {
+                mut exprs if exprs.len() == 1 => return exprs.pop(),
+                exprs => ExpressionKind::Block(exprs),
+            },
+            SyntaxKind::IfExpr => {
+                let mut operands = operands(self)?.into_iter();
+                ExpressionKind::Conditional {
+                    cond_expr: Box::new(operands.next()?),
+                    if_block_expr: Box::new(operands.next()?),
+                    else_block_expr: operands.next().map(Box::new),
+                }
+            }
+            SyntaxKind::VarExpr => {
+                let variables = expr
+                    .children()
+                    .filter(|node| node.kind() == SyntaxKind::VarBinding)
+                    .map(|binding| {
+                        let name = token(&binding, SyntaxKind::Ident)?.text().to_string();
+                        let initializer = match binding.children().next() {
+                            Some(initializer) => Some(self.lower_expr(&initializer)?),
+                            None => None,
+                        };
+                        Some((name, initializer))
+                    })
+                    .collect::<Option<_>>()?;
+                ExpressionKind::VarIn {
+                    variables,
+                    body: Box::new(operands(self)?.pop()?),
+                }
+            }
+            SyntaxKind::ForExpr => {
+                let var_name = token(expr, SyntaxKind::Ident)?.text().to_string();
+                let mut operands = operands(self)?;
+                let body = Box::new(operands.pop()?);
+                let step = match operands.len() {
+                    3 => Some(Box::new(operands.pop()?)),
+                    _ => None,
+                };
+                let [start, cond]: [Expression; 2] = operands.try_into().ok()?;
+                ExpressionKind::For {
+                    var_name,
+                    start: Box::new(start),
+                    cond: Box::new(cond),
+                    step,
+                    body,
+                }
+            }
+            SyntaxKind::WhileExpr => {
+                let [cond, body]: [Expression; 2] = operands(self)?.try_into().ok()?;
+                ExpressionKind::While {
+                    cond: Box::new(cond),
+                    body: Box::new(body),
+                }
+            }
+            _ => return None,
+        };
+        Some(Expression {
+            kind,
+            span: self.span(expr.text_range()),
+            id: self.new_id(),
+        })
+    }
+}
+
+/// The first child node of `node` of the given `kind`.
+fn child(node: &SyntaxNode, kind: SyntaxKind) -> Option<SyntaxNode> {
+    node.children().find(|child| child.kind() == kind)
+}
+
+/// The first token directly below `node` of the given `kind`.
+fn token(node: &SyntaxNode, kind: SyntaxKind) -> Option<SyntaxToken> {
+    node.child_tokens().find(|token| token.kind() == kind)
+}
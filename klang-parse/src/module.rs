@@ -0,0 +1,80 @@
+use crate::{
+    error::{Expected, ParseError},
+    parse::{Parse, ParseStop, Parser, PartParsingResult},
+    parser::{recover, register_operator, ParserSettings},
+    syntax::SyntaxKind,
+    token::Token,
+};
+use klang_ast::{
+    function::Function,
+    node::{ASTNode, Module},
+};
+
+/// Parses `mod name { items }`.
+///
+/// Modules hold functions and nested modules. Externs name symbols defined
+/// outside the program, which have no module, so they are declared at the top
+/// level only. Like top-level items, operators defined in a module can be used
+/// by the items following them.
+///
+/// Broken items are skipped up to the next item or the module's `}`, and
+/// their errors are added to `errors` once the module is complete.
+pub(crate) fn parse_module(
+    parser: &mut Parser,
+    settings: &mut ParserSettings,
+    errors: &mut Vec<ParseError>,
+) -> PartParsingResult<ASTNode> {
+    let marker = parser.start();
+    // Consume `mod` keyword.
+    parser.bump();
+    let name = parser.expect_ident()?.to_string();
+    parser.expect(Token::OpeningBrace)?;
+
+    let mut items = Vec::new();
+    let mut item_errors = Vec::new();
+    loop {
+        let item_start = parser.checkpoint();
+        let result = match parser.peek_token() {
+            Some(Token::ClosingBrace) => {
+                parser.bump();
+                break;
+            }
+            Some(Token::Delimiter) => {
+                parser.bump();
+                continue;
+            }
+            Some(Token::Fun | Token::Pub) => Function::parse(parser, settings),
+            Some(Token::Mod) => parse_module(parser, settings, &mut item_errors),
+            _ => {
+                let found = parser.next_token()?;
+                let expected = Expected::OneOf(vec![
+                    Expected::Token(Token::Fun),
+                    Expected::Token(Token::Pub),
+                    Expected::Token(Token::Mod),
+                    Expected::Token(Token::ClosingBrace),
+                ]);
+                Err(ParseError::unexpected(expected, found).into())
+            }
+        };
+        match result {
+            Ok(item) => {
+                register_operator(settings, &item);
+                items.push(item);
+            }
+            Err(ParseStop::Bad(error)) => {
+                item_errors.push(error);
+                recover(parser, item_start, true);
+            }
+            Err(stop @ ParseStop::NotComplete) => return Err(stop),
+        }
+    }
+    let (span, id) = parser.finish_node(marker, SyntaxKind::Module);
+    errors.append(&mut item_errors);
+
+    Ok(ASTNode::ModuleNode(Module {
+        name,
+        items,
+        span,
+        id,
+    }))
+}
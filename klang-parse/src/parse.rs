@@ -1,74 +1,301 @@
-use crate::token::Token;
-use klang_ast::node::ASTNode;
+#[cfg(test)]
+use std::cell::Cell;
 
-pub type ParsingResult = Result<(Vec<ASTNode>, Vec<Token>), String>;
+use crate::{
+    error::{Expected, ParseError, ParseFailure},
+    event::Event,
+    parser::ParserSettings,
+    syntax::SyntaxKind,
+    token::{SpannedToken, Token},
+};
+use klang_ast::{id::NodeId, node::ASTNode, span::Span};
 
-pub(crate) enum PartParsingResult<T> {
-    Good(T, Vec<Token>),
+/// The parsed nodes, and the tokens left over after the last complete item.
+pub type ParsingResult<'t, 'src> = Result<(Vec<ASTNode>, &'t [SpannedToken<'src>]), ParseFailure>;
+
+/// Why an item could not be parsed.
+pub(crate) enum ParseStop {
+    /// The tokens ran out before the item was complete.
     NotComplete,
-    Bad(String),
+    Bad(ParseError),
+}
+
+impl From<ParseError> for ParseStop {
+    fn from(error: ParseError) -> Self {
+        ParseStop::Bad(error)
+    }
+}
+
+pub(crate) type PartParsingResult<T> = Result<T, ParseStop>;
+
+/// A node started by `Parser::start`, to be completed with `Parser::finish`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Marker {
+    event: usize,
+    /// Index of the node's first token.
+    position: usize,
 }
 
-/// TODO REMOVE THIS
-pub(crate) fn error<T>(message: &str) -> PartParsingResult<T> {
-    PartParsingResult::Bad(message.to_string())
+/// Parser state to go back to with `Parser::rewind`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Checkpoint {
+    position: usize,
+    events: usize,
+    next_id: NodeId,
 }
 
-#[macro_export]
-macro_rules! expect_token (
-    ([ $($token:pat, $value:expr, $result:stmt);+ ] <= $tokens:ident, $parsed_tokens:ident, $error:expr) => (
-        match $tokens.pop() {
-            $(
-                Some($token) => {
-                    $parsed_tokens.push($value);
-                    $result
-                },
-             )+
-             None => {
-                 $parsed_tokens.reverse();
-                 $tokens.extend($parsed_tokens.into_iter());
-                 return PartParsingResult::NotComplete;
-             },
-            _ => return error($error)
+impl Checkpoint {
+    /// Index of the next token at the time of the checkpoint.
+    pub(crate) fn position(self) -> usize {
+        self.position
+    }
+}
+
+/// Cursor over a borrowed token slice.
+///
+/// Parsing only moves `position` forward. An item that runs out of tokens is
+/// abandoned by moving the cursor back to where the item started, so tokens
+/// are never copied or given back.
+///
+/// When created with `with_events`, the parser also records where syntax
+/// nodes start and end, to build a lossless syntax tree from.
+pub(crate) struct Parser<'t, 'src> {
+    tokens: &'t [SpannedToken<'src>],
+    position: usize,
+    events: Option<Vec<Event>>,
+    /// Id of the next completed AST node.
+    next_id: NodeId,
+    /// How often tokens were looked at, which grows linearly with the input.
+    #[cfg(test)]
+    visits: Cell<usize>,
+}
+
+impl<'t, 'src> Parser<'t, 'src> {
+    pub(crate) fn new(tokens: &'t [SpannedToken<'src>]) -> Self {
+        Self {
+            tokens,
+            position: 0,
+            events: None,
+            next_id: NodeId::default(),
+            #[cfg(test)]
+            visits: Cell::new(0),
+        }
+    }
+
+    pub(crate) fn with_events(tokens: &'t [SpannedToken<'src>]) -> Self {
+        Self {
+            events: Some(Vec::new()),
+            ..Self::new(tokens)
         }
-    );
-
-    ([ $($token:pat, $value:expr, $result:stmt);+ ] else $not_matched:block <= $tokens:ident, $parsed_tokens:ident) => (
-        match $tokens.last().map(|i| {i.clone()}) {
-            $(
-                Some($token) => {
-                    $tokens.pop();
-                    $parsed_tokens.push($value);
-                    $result
-                },
-             )+
-            _ => {$not_matched}
+    }
+
+    /// The recorded syntax tree events, empty unless created `with_events`.
+    pub(crate) fn into_events(self) -> Vec<Event> {
+        self.events.unwrap_or_default()
+    }
+
+    /// Opens a syntax node in front of the next token.
+    pub(crate) fn start(&mut self) -> Marker {
+        let marker = self.next_marker();
+        let position = self.position;
+        if let Some(events) = &mut self.events {
+            events.push(Event::Start {
+                kind: SyntaxKind::Error,
+                position,
+                forward_parent: None,
+            });
+        }
+        marker
+    }
+
+    /// The marker the next call to `start` returns.
+    pub(crate) fn next_marker(&self) -> Marker {
+        Marker {
+            event: self.events.as_ref().map_or(0, Vec::len),
+            position: self.position,
+        }
+    }
+
+    /// Closes the node of `marker` behind the last consumed token.
+    pub(crate) fn finish(&mut self, marker: Marker, kind: SyntaxKind) {
+        let position = self.position;
+        if let Some(events) = &mut self.events {
+            if let Event::Start {
+                kind: start_kind, ..
+            } = &mut events[marker.event]
+            {
+                *start_kind = kind;
+            }
+            events.push(Event::Finish { position });
         }
-    )
-);
-
-#[macro_export]
-macro_rules! parse_try(
-    ($function:ident, $tokens:ident, $parsed_tokens:ident) => (
-        parse_try!($function, $tokens, $parsed_tokens,)
-    );
-
-    ($function:ident, $tokens:ident, $parsed_tokens:ident, $($arg:expr),*) => (
-        match $function {
-            PartParsingResult::Good(ast, toks) => {
-                $parsed_tokens.extend(toks.into_iter());
-                ast
-            },
-            PartParsingResult::NotComplete => {
-                $parsed_tokens.reverse();
-                $tokens.extend($parsed_tokens.into_iter());
-                return PartParsingResult::NotComplete;
-            },
-            PartParsingResult::Bad(message) => return PartParsingResult::Bad(message)
+    }
+
+    /// Closes the node of `marker` like `finish`, returning the span of its
+    /// tokens and a new id for the AST node parsed from them.
+    pub(crate) fn finish_node(&mut self, marker: Marker, kind: SyntaxKind) -> (Span, NodeId) {
+        self.finish(marker, kind);
+        (self.span_since(marker), self.new_id())
+    }
+
+    /// Span of the tokens consumed since `marker` was started.
+    pub(crate) fn span_since(&self, marker: Marker) -> Span {
+        match self.consumed_since(marker.position) {
+            [first, .., last] => first.span.to(last.span),
+            [only] => only.span,
+            [] => Span::default(),
+        }
+    }
+
+    /// Gives out the id of the next completed AST node.
+    pub(crate) fn new_id(&mut self) -> NodeId {
+        let id = self.next_id;
+        self.next_id = id.next();
+        id
+    }
+
+    /// Opens a node wrapping the already finished node of `marker`.
+    pub(crate) fn precede(&mut self, marker: Marker) -> Marker {
+        let new_marker = Marker {
+            position: marker.position,
+            ..self.next_marker()
+        };
+        if let Some(events) = &mut self.events {
+            let Event::Start {
+                position,
+                forward_parent,
+                ..
+            } = &mut events[marker.event]
+            else {
+                unreachable!("marker does not point to a node start");
+            };
+            *forward_parent = Some(new_marker.event);
+            let position = *position;
+            events.push(Event::Start {
+                kind: SyntaxKind::Error,
+                position,
+                forward_parent: None,
+            });
         }
-    )
-);
+        new_marker
+    }
+
+    pub(crate) fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            position: self.position,
+            events: self.next_marker().event,
+            next_id: self.next_id,
+        }
+    }
+
+    /// Forgets everything parsed since `checkpoint` was taken.
+    pub(crate) fn rewind(&mut self, checkpoint: Checkpoint) {
+        self.position = checkpoint.position;
+        self.next_id = checkpoint.next_id;
+        if let Some(events) = &mut self.events {
+            events.truncate(checkpoint.events);
+        }
+    }
+
+    /// Id of the next completed AST node.
+    pub(crate) fn next_id(&self) -> NodeId {
+        self.next_id
+    }
+
+    /// Continues numbering AST nodes from `id`.
+    pub(crate) fn set_next_id(&mut self, id: NodeId) {
+        self.next_id = id;
+    }
+
+    /// Index of the next token.
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Moves the cursor back to `position`, as returned by `position`.
+    pub(crate) fn reset(&mut self, position: usize) {
+        self.position = position;
+    }
+
+    /// Tokens consumed since the cursor was at `position`.
+    pub(crate) fn consumed_since(&self, position: usize) -> &'t [SpannedToken<'src>] {
+        &self.tokens[position..self.position]
+    }
+
+    /// Tokens not consumed yet.
+    pub(crate) fn remaining(&self) -> &'t [SpannedToken<'src>] {
+        &self.tokens[self.position..]
+    }
+
+    /// The next token, without consuming it.
+    pub(crate) fn peek(&self) -> Option<&'t SpannedToken<'src>> {
+        #[cfg(test)]
+        self.visits.set(self.visits.get() + 1);
+        self.tokens.get(self.position)
+    }
+
+    /// How often tokens were looked at so far.
+    #[cfg(test)]
+    pub(crate) fn token_visits(&self) -> usize {
+        self.visits.get()
+    }
+
+    pub(crate) fn peek_token(&self) -> Option<&'t Token<'src>> {
+        self.peek().map(|next| &next.token)
+    }
+
+    /// Consumes the next token, if any.
+    pub(crate) fn bump(&mut self) -> Option<&'t SpannedToken<'src>> {
+        let next = self.peek()?;
+        self.position += 1;
+        Some(next)
+    }
+
+    /// Consumes the next token, the item is not complete without one.
+    pub(crate) fn next_token(&mut self) -> PartParsingResult<&'t SpannedToken<'src>> {
+        self.bump().ok_or(ParseStop::NotComplete)
+    }
+
+    /// Consumes the next token if it is `token`.
+    pub(crate) fn eat(&mut self, token: &Token) -> bool {
+        let matches = self.peek_token() == Some(token);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    /// Consumes the next token, failing unless it is `token`.
+    pub(crate) fn expect(&mut self, token: Token<'static>) -> PartParsingResult<()> {
+        let next = self.next_token()?;
+        if next.token != token {
+            return Err(ParseError::unexpected(Expected::Token(token), next).into());
+        }
+        Ok(())
+    }
+
+    /// Consumes the next token, failing unless it is an identifier.
+    pub(crate) fn expect_ident(&mut self) -> PartParsingResult<&'t str> {
+        match self.next_token()? {
+            SpannedToken {
+                token: Token::Ident(name),
+                ..
+            } => Ok(name),
+            found => Err(ParseError::unexpected(Expected::Identifier, found).into()),
+        }
+    }
+
+    /// Consumes the next token, failing unless it is a string literal.
+    pub(crate) fn expect_string(&mut self) -> PartParsingResult<&'t str> {
+        match self.next_token()? {
+            SpannedToken {
+                token: Token::String(text),
+                ..
+            } => Ok(text),
+            found => Err(ParseError::unexpected(Expected::String, found).into()),
+        }
+    }
+}
 
 pub(crate) trait Parse<T> {
-    fn parse(tokens: &mut Vec<Token>) -> PartParsingResult<T>;
+    fn parse(parser: &mut Parser, settings: &ParserSettings) -> PartParsingResult<T>;
 }
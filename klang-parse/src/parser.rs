@@ -1,58 +1,377 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
-    parse::{Parse, ParsingResult, PartParsingResult},
-    token::Token,
+    error::{ParseError, ParseFailure},
+    event::build_tree,
+    import::parse_import,
+    lexer::Lexer,
+    module::parse_module,
+    parse::{Checkpoint, Parse, ParseStop, Parser, ParsingResult},
+    prototype::is_builtin_operator,
+    syntax::{SyntaxKind, SyntaxNode, SyntaxTree},
+    token::{SpannedToken, Token},
 };
 use klang_ast::{
     expr::Expression,
-    function::{Function, Prototype},
+    function::{Function, Prototype, PrototypeKind},
+    id::NodeId,
     node::ASTNode,
+    op::{BUILTIN_BINARY_OPERATORS, BUILTIN_UNARY_OPERATORS},
+    print::Printer,
+    span::FileId,
 };
 
-#[derive(Debug)]
+pub use klang_ast::op::{Associativity, BinaryOperator};
+
+/// Configures the parser, most notably the set of known operators.
+#[derive(Clone, Debug)]
 pub struct ParserSettings {
-    pub operator_precedence: HashMap<String, i32>,
+    pub binary_operators: HashMap<String, BinaryOperator>,
+    /// Precedence of prefix operators. Binary operators binding tighter than
+    /// a prefix operator are part of its operand.
+    pub unary_operators: HashMap<String, i32>,
+    /// Prefix operators declared with `unary<op>`, which have no precedence
+    /// and apply to the closest operand only.
+    pub user_unary_operators: HashSet<String>,
+    /// How deep expressions may nest, counting parentheses, blocks and
+    /// operators still waiting for their right operand. Keeps the parser and
+    /// later passes walking the tree from overflowing the stack.
+    pub max_nesting_depth: usize,
+    /// Id of the next parsed AST node. Parsing continues numbering from
+    /// here, so nodes parsed with the same settings, for example from the
+    /// files of one program, never share an id.
+    pub next_node_id: NodeId,
+}
+
+impl ParserSettings {
+    /// Registers `op`, replacing any previous definition of it.
+    pub fn add_binary_operator(
+        &mut self,
+        op: impl Into<String>,
+        precedence: i32,
+        associativity: Associativity,
+    ) {
+        let operator = BinaryOperator {
+            precedence,
+            associativity,
+        };
+        self.binary_operators.insert(op.into(), operator);
+    }
+
+    /// Looks up the rules of the binary operator `op`.
+    pub fn binary_operator(&self, op: &str) -> Option<&BinaryOperator> {
+        self.binary_operators.get(op)
+    }
+
+    /// Registers the prefix operator `op`, replacing any previous definition of it.
+    pub fn add_unary_operator(&mut self, op: impl Into<String>, precedence: i32) {
+        self.unary_operators.insert(op.into(), precedence);
+    }
+
+    /// Looks up the precedence of the prefix operator `op`.
+    pub fn unary_operator(&self, op: &str) -> Option<i32> {
+        self.unary_operators.get(op).copied()
+    }
+
+    /// Registers the prefix operator `op` declared by a `unary<op>` function.
+    pub fn add_user_unary_operator(&mut self, op: impl Into<String>) {
+        self.user_unary_operators.insert(op.into());
+    }
+
+    /// Whether `op` can be used as a prefix operator.
+    pub fn is_unary_operator(&self, op: &str) -> bool {
+        self.unary_operators.contains_key(op) || self.user_unary_operators.contains(op)
+    }
+
+    /// Registers the operators defined by `items` and the items of their
+    /// modules, as if they had just been parsed.
+    pub fn register_operators(&mut self, items: &[ASTNode]) {
+        for item in items {
+            match item {
+                ASTNode::ModuleNode(module) => self.register_operators(&module.items),
+                item => register_operator(self, item),
+            }
+        }
+    }
+
+    /// A printer grouping operators the way these settings parse them.
+    pub fn printer(&self) -> Printer {
+        Printer::with_operators(self.binary_operators.clone(), self.unary_operators.clone())
+    }
 }
 
 impl Default for ParserSettings {
     fn default() -> Self {
-        let mut operator_precedence = HashMap::new();
-        operator_precedence.insert("<".to_string(), 10);
-        operator_precedence.insert("+".to_string(), 20);
-        operator_precedence.insert("-".to_string(), 20);
-        operator_precedence.insert("*".to_string(), 40);
-
-        Self {
-            operator_precedence,
+        let mut settings = Self {
+            binary_operators: HashMap::new(),
+            unary_operators: HashMap::new(),
+            user_unary_operators: HashSet::new(),
+            max_nesting_depth: 256,
+            next_node_id: NodeId::default(),
+        };
+        for (op, precedence, associativity) in BUILTIN_BINARY_OPERATORS {
+            settings.add_binary_operator(op, precedence, associativity);
         }
+        for (op, precedence) in BUILTIN_UNARY_OPERATORS {
+            settings.add_unary_operator(op, precedence);
+        }
+
+        settings
     }
 }
 
-#[allow(dead_code)]
-pub fn parse(tokens: &[Token], parsed_tree: &[ASTNode]) -> ParsingResult {
-    let mut token_stream = tokens.to_vec();
-    token_stream.reverse();
+pub fn parse<'t, 'src>(
+    tokens: &'t [SpannedToken<'src>],
+    parsed_tree: &[ASTNode],
+) -> ParsingResult<'t, 'src> {
+    parse_with_settings(tokens, parsed_tree, &mut ParserSettings::default())
+}
+
+/// Parses `tokens` like `parse`, using `settings` instead of the defaults.
+///
+/// User-defined operators are registered in `settings` as they are
+/// parsed, so they stay available when parsing continues with more tokens.
+pub fn parse_with_settings<'t, 'src>(
+    tokens: &'t [SpannedToken<'src>],
+    parsed_tree: &[ASTNode],
+    settings: &mut ParserSettings,
+) -> ParsingResult<'t, 'src> {
+    let mut parser = Parser::new(tokens);
     let mut parsed_tree = parsed_tree.to_vec();
+    let mut errors = Vec::new();
+    parse_items(&mut parser, settings, &mut parsed_tree, &mut errors);
+
+    if !errors.is_empty() {
+        return Err(ParseFailure {
+            errors,
+            nodes: parsed_tree,
+        });
+    }
+
+    Ok((parsed_tree, parser.remaining()))
+}
+
+/// Parses `tokens` like `parse_with_settings`, stopping after the first
+/// import declaration.
+///
+/// The tokens after the import are returned, to be parsed once the imported
+/// file is loaded and its operators are registered in `settings`.
+pub fn parse_to_import<'t, 'src>(
+    tokens: &'t [SpannedToken<'src>],
+    settings: &mut ParserSettings,
+) -> ParsingResult<'t, 'src> {
+    let mut parser = Parser::new(tokens);
+    let mut parsed_tree = Vec::new();
+    let mut errors = Vec::new();
+    parse_items_until(&mut parser, settings, &mut parsed_tree, &mut errors, true);
+
+    if !errors.is_empty() {
+        return Err(ParseFailure {
+            errors,
+            nodes: parsed_tree,
+        });
+    }
+
+    Ok((parsed_tree, parser.remaining()))
+}
 
-    while let Some(token) = token_stream.last() {
+/// Parses `input` into a lossless syntax tree, keeping comments and whitespace.
+///
+/// Unlike `parse`, this never fails. Errors are reported in the tree, and the
+/// text they affect ends up in `SyntaxKind::Error` nodes and
+/// `SyntaxKind::Unknown` tokens.
+pub fn parse_syntax(input: &str) -> SyntaxTree {
+    parse_syntax_with_settings(input, &mut ParserSettings::default())
+}
+
+/// Parses `input` like `parse_syntax`, using `settings` instead of the defaults.
+pub fn parse_syntax_with_settings(input: &str, settings: &mut ParserSettings) -> SyntaxTree {
+    parse_syntax_file(FileId::default(), input, settings)
+}
+
+/// Parses `input` like `parse_syntax_with_settings`, giving the spans of the
+/// tree's nodes to `file`.
+pub fn parse_syntax_file(file: FileId, input: &str, settings: &mut ParserSettings) -> SyntaxTree {
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    for token in Lexer::new(file, input) {
+        match token {
+            Ok(token) => tokens.push(token),
+            Err(error) => errors.push(ParseError::Lex(error)),
+        }
+    }
+
+    let first_id = settings.next_node_id;
+    let mut parser = Parser::with_events(&tokens);
+    parse_items(&mut parser, settings, &mut Vec::new(), &mut errors);
+    if let Some(last) = tokens.last().filter(|_| parser.peek().is_some()) {
+        errors.push(ParseError::UnexpectedEnd { span: last.span });
+        let marker = parser.start();
+        while parser.bump().is_some() {}
+        parser.finish(marker, SyntaxKind::Error);
+    }
+    errors.sort_by_key(|error| error.span().start);
+
+    let green = build_tree(input, &tokens, parser.into_events());
+    SyntaxTree {
+        root: SyntaxNode::new_root(green),
+        errors,
+        file,
+        first_id,
+    }
+}
+
+/// Parses the complete items ahead of `parser` into `parsed_tree`, recovering
+/// from errors by collecting them into `errors`.
+///
+/// Stops in front of the trailing item that is not complete yet.
+pub(crate) fn parse_items(
+    parser: &mut Parser,
+    settings: &mut ParserSettings,
+    parsed_tree: &mut Vec<ASTNode>,
+    errors: &mut Vec<ParseError>,
+) {
+    parse_items_until(parser, settings, parsed_tree, errors, false);
+}
+
+/// Parses items like `parse_items`, stopping after the first import
+/// declaration if `stop_at_import` is set.
+fn parse_items_until(
+    parser: &mut Parser,
+    settings: &mut ParserSettings,
+    parsed_tree: &mut Vec<ASTNode>,
+    errors: &mut Vec<ParseError>,
+    stop_at_import: bool,
+) {
+    parser.set_next_id(settings.next_node_id);
+    while let Some(token) = parser.peek_token() {
+        let item_start = parser.checkpoint();
         let result = match token {
-            Token::Fun => Function::parse(&mut token_stream),
-            Token::Use => Prototype::parse(&mut token_stream),
+            Token::Fun | Token::Pub => Function::parse(parser, settings),
+            Token::Use => Prototype::parse(parser, settings),
+            Token::Import => parse_import(parser),
+            Token::Mod => parse_module(parser, settings, errors),
             Token::Delimiter => {
-                token_stream.pop();
+                parser.bump();
                 continue;
             }
-            _ => Expression::parse(&mut token_stream),
+            _ => Expression::parse(parser, settings),
         };
 
         match result {
-            PartParsingResult::Good(ast_node, _) => parsed_tree.push(ast_node),
-            PartParsingResult::NotComplete => break,
-            PartParsingResult::Bad(message) => return Err(message),
+            Ok(ast_node) => {
+                register_operator(settings, &ast_node);
+                let is_import = matches!(ast_node, ASTNode::ImportNode(_));
+                parsed_tree.push(ast_node);
+                if stop_at_import && is_import {
+                    break;
+                }
+            }
+            Err(ParseStop::NotComplete) => {
+                parser.rewind(item_start);
+                break;
+            }
+            Err(ParseStop::Bad(error)) => {
+                errors.push(error);
+                recover(parser, item_start, false);
+            }
         }
     }
+    settings.next_node_id = parser.next_id();
+}
 
-    token_stream.reverse();
-    Ok((parsed_tree, token_stream))
+/// Registers the operator `item` defines, if any, so the items after it can
+/// use the operator.
+pub(crate) fn register_operator(settings: &mut ParserSettings, item: &ASTNode) {
+    let prototype = match item {
+        ASTNode::ExternNode(prototype) => prototype,
+        ASTNode::FunctionNode(function) => &function.prototype,
+        // modules register the operators of their items as they are parsed
+        ASTNode::ImportNode(_) | ASTNode::ModuleNode(_) => return,
+    };
+    // the parser rejects these, but ASTs loaded from elsewhere may hold them
+    match (&prototype.kind, prototype.operator()) {
+        (PrototypeKind::BinaryOperator { .. }, Some(op)) if is_builtin_operator("binary", op) => {}
+        (PrototypeKind::UnaryOperator, Some(op)) if is_builtin_operator("unary", op) => {}
+        (PrototypeKind::BinaryOperator { precedence }, Some(op)) => {
+            settings.add_binary_operator(op, *precedence, Associativity::Left)
+        }
+        (PrototypeKind::UnaryOperator, Some(op)) => settings.add_user_unary_operator(op),
+        _ => {}
+    }
+}
+
+/// Replaces the nodes of the broken item that started at `item_start` with a
+/// single error node, skipping ahead to where parsing can resume.
+///
+/// Inside a module, the `}` closing the module is left for it to parse.
+pub(crate) fn recover(parser: &mut Parser, item_start: Checkpoint, in_module: bool) {
+    let failed_at = parser.position();
+    parser.rewind(item_start);
+    let marker = parser.start();
+    parser.reset(failed_at);
+    synchronize(parser, item_start.position(), in_module);
+    parser.finish(marker, SyntaxKind::Error);
+}
+
+/// Skips tokens after a parse error until a point where parsing can resume.
+///
+/// The failed item started at `item_start`. Parsing resumes before the next
+/// `fun`, `pub`, `use`, `import` or `mod`, or after the `;` or `}` that ends the broken item.
+/// `in_module` stops before a `}` closing the enclosing module.
+fn synchronize(parser: &mut Parser, item_start: usize, in_module: bool) {
+    let consumed = parser.consumed_since(item_start);
+    let mut depth = consumed.iter().fold(0, |depth, t| match t.token {
+        Token::OpeningBrace => depth + 1,
+        Token::ClosingBrace => depth - 1,
+        _ => depth,
+    });
+    // the item failed on the module's `}`, give it back
+    if in_module && depth < 0 {
+        parser.reset(parser.position() - 1);
+        return;
+    }
+
+    match consumed.split_last() {
+        // always make progress, a stray terminator is skipped on its own
+        None => {
+            if let Some(SpannedToken {
+                token: Token::Delimiter | Token::ClosingBrace,
+                ..
+            }) = parser.bump()
+            {
+                return;
+            }
+        }
+        // the failing token may start the next item, give it back
+        Some((last, rest))
+            if !rest.is_empty()
+                && matches!(
+                    last.token,
+                    Token::Fun | Token::Pub | Token::Use | Token::Import | Token::Mod
+                ) =>
+        {
+            parser.reset(parser.position() - 1);
+        }
+        _ => {}
+    }
+
+    while let Some(token) = parser.peek_token() {
+        match token {
+            Token::Fun | Token::Pub | Token::Use | Token::Import | Token::Mod => return,
+            Token::ClosingBrace if in_module && depth <= 0 => return,
+            Token::OpeningBrace => depth += 1,
+            Token::ClosingBrace if depth <= 1 => {
+                parser.bump();
+                return;
+            }
+            Token::ClosingBrace => depth -= 1,
+            Token::Delimiter if depth <= 0 => {
+                parser.bump();
+                return;
+            }
+            _ => {}
+        }
+        parser.bump();
+    }
 }
@@ -1,35 +1,138 @@
 use crate::{
-    expect_token,
-    parse::{error, Parse, PartParsingResult},
-    token::Token,
+    error::{Expected, ParseError},
+    parse::{Parse, Parser, PartParsingResult},
+    parser::ParserSettings,
+    syntax::SyntaxKind,
+    token::{SpannedToken, Token},
 };
-use klang_ast::function::Prototype;
+use klang_ast::{
+    function::{Prototype, PrototypeKind},
+    op::{BUILTIN_BINARY_OPERATORS, BUILTIN_UNARY_OPERATORS},
+};
+
+/// Precedence of user-defined binary operators declared without one.
+pub(crate) const DEFAULT_OPERATOR_PRECEDENCE: i32 = 30;
+
+/// Whether `unary<operator>` or `binary<operator>`, depending on `kind`,
+/// names an operator the compiler already implements.
+pub(crate) fn is_builtin_operator(kind: &str, operator: &str) -> bool {
+    if kind == "unary" {
+        BUILTIN_UNARY_OPERATORS
+            .iter()
+            .any(|(op, _)| *op == operator)
+    } else {
+        BUILTIN_BINARY_OPERATORS
+            .iter()
+            .any(|(op, ..)| *op == operator)
+    }
+}
 
 impl Parse<Prototype> for Prototype {
-    fn parse(tokens: &mut Vec<Token>) -> PartParsingResult<Prototype> {
-        let mut parsed_tokens = Vec::new();
+    fn parse(parser: &mut Parser, _settings: &ParserSettings) -> PartParsingResult<Prototype> {
+        let marker = parser.start();
+        let name_span = parser.peek().map(|t| t.span).unwrap_or_default();
+        let mut name = parser.expect_ident()?.to_string();
 
-        let name = expect_token!(
-            [Token::Ident(name), Token::Ident(name.clone()), name] <= tokens,
-            parsed_tokens,
-            "expected function name in prototype"
-        );
+        // `unary<op>` and `binary<op> <precedence>` declare operators
+        let mut kind = PrototypeKind::Function;
+        if name == "unary" || name == "binary" {
+            if let Some(SpannedToken {
+                token: Token::Operator(operator),
+                span,
+            }) = parser.peek()
+            {
+                if is_builtin_operator(&name, operator) {
+                    return Err(ParseError::BuiltinOperator {
+                        operator: operator.to_string(),
+                        span: *span,
+                    }
+                    .into());
+                }
+                parser.bump();
+                kind = if name == "unary" {
+                    PrototypeKind::UnaryOperator
+                } else {
+                    let precedence = match parser.peek() {
+                        Some(SpannedToken {
+                            token: Token::Number(value),
+                            span,
+                        }) => {
+                            parser.bump();
+                            Some((*value, *span))
+                        }
+                        _ => None,
+                    };
 
-        expect_token!(
-            [Token::OpeningParenthesis, Token::OpeningParenthesis, ()] <= tokens,
-            parsed_tokens,
-            "expected '(' in prototype"
-        );
+                    let precedence = match precedence {
+                        None => DEFAULT_OPERATOR_PRECEDENCE,
+                        Some((value, _))
+                            if value.fract() == 0.0 && (1.0..=100.0).contains(&value) =>
+                        {
+                            value as i32
+                        }
+                        Some((value, span)) => {
+                            return Err(ParseError::InvalidPrecedence { value, span }.into())
+                        }
+                    };
+                    PrototypeKind::BinaryOperator { precedence }
+                };
+                name.push_str(operator);
+            }
+        }
+
+        let parameters_marker = parser.start();
+        parser.expect(Token::OpeningParenthesis)?;
 
         let mut args = Vec::new();
         loop {
-            expect_token!([
-            Token::Ident(arg), Token::Ident(arg.clone()), args.push(arg.clone());
-            Token::Comma, Token::Comma, continue;
-            Token::ClosingParenthesis, Token::ClosingParenthesis, break
-        ] <= tokens, parsed_tokens, "expected ')' in prototype");
+            match parser.next_token()? {
+                SpannedToken {
+                    token: Token::Ident(arg),
+                    ..
+                } => args.push(arg.to_string()),
+                SpannedToken {
+                    token: Token::Comma,
+                    ..
+                } => continue,
+                SpannedToken {
+                    token: Token::ClosingParenthesis,
+                    ..
+                } => break,
+                found => {
+                    let expected = Expected::OneOf(vec![
+                        Expected::Identifier,
+                        Expected::Token(Token::Comma),
+                        Expected::Token(Token::ClosingParenthesis),
+                    ]);
+                    return Err(ParseError::unexpected(expected, found).into());
+                }
+            }
+        }
+
+        parser.finish(parameters_marker, SyntaxKind::ParameterList);
+
+        let arity = match kind {
+            PrototypeKind::Function => args.len(),
+            PrototypeKind::UnaryOperator => 1,
+            PrototypeKind::BinaryOperator { .. } => 2,
+        };
+        if args.len() != arity {
+            return Err(ParseError::OperatorArity {
+                operator: name,
+                expected: arity,
+                found: args.len(),
+                span: name_span,
+            }
+            .into());
         }
 
-        PartParsingResult::Good(Prototype { name, args }, parsed_tokens)
+        let (span, id) = parser.finish_node(marker, SyntaxKind::Prototype);
+        Ok(Prototype {
+            name,
+            args,
+            kind,
+            span,
+            id,
+        })
     }
 }
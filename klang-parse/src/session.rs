@@ -0,0 +1,125 @@
+use crate::{
+    error::{ParseError, ParseFailure},
+    lexer::Lexer,
+    parse::Parser,
+    parser::{parse_items, ParserSettings},
+};
+use klang_ast::{node::ASTNode, span::FileId};
+
+/// Parses source text handed over in chunks, like the lines typed into a REPL.
+///
+/// Items complete at the end of a chunk are handed out right away. The text of
+/// a trailing item that is not complete yet is kept and parsed again together
+/// with the next chunk. Settings persist across chunks, so operators defined in
+/// one chunk can be used by the following ones.
+#[derive(Clone, Debug)]
+pub struct ParseSession {
+    file: FileId,
+    settings: ParserSettings,
+    /// Source text of the incomplete trailing item.
+    pending: String,
+    /// Byte offset, line and column of `pending` in the whole input.
+    pending_start: (usize, u32, u32),
+}
+
+impl ParseSession {
+    /// Starts a session attributing all spans to `file`.
+    pub fn new(file: FileId, settings: ParserSettings) -> Self {
+        Self {
+            file,
+            settings,
+            pending: String::new(),
+            pending_start: (0, 1, 1),
+        }
+    }
+
+    /// The settings, including the operators defined so far.
+    pub fn settings(&self) -> &ParserSettings {
+        &self.settings
+    }
+
+    /// Whether the input so far ends in the middle of an item.
+    pub fn needs_more_input(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Parses `chunk` as the continuation of the input so far, returning the
+    /// items it completed.
+    ///
+    /// Errors don't end the session. The items that parsed despite them are
+    /// returned in `ParseFailure::nodes`.
+    ///
+    /// Text that doesn't lex is reported with the chunk it is in. The item
+    /// holding it stays invalid: once complete, it is left out of the parsed
+    /// items and the error is reported again.
+    pub fn push_str(&mut self, chunk: &str) -> Result<Vec<ASTNode>, ParseFailure> {
+        let (offset, line, column) = self.pending_start;
+        let chunk_start = offset + self.pending.len();
+        self.pending.push_str(chunk);
+
+        let mut tokens = Vec::new();
+        let mut lex_errors = Vec::new();
+        for token in Lexer::new(self.file, &self.pending).starting_at(offset, line, column) {
+            match token {
+                Ok(token) => tokens.push(token),
+                Err(error) => lex_errors.push(error),
+            }
+        }
+        // errors in buffered text were reported along with their chunk
+        let mut errors: Vec<_> = lex_errors
+            .iter()
+            .filter(|error| error.span().start >= chunk_start)
+            .cloned()
+            .map(ParseError::Lex)
+            .collect();
+
+        let mut parser = Parser::new(&tokens);
+        let mut nodes = Vec::new();
+        parse_items(&mut parser, &mut self.settings, &mut nodes, &mut errors);
+        nodes.retain(|node| {
+            let span = node.span();
+            let mut inside = lex_errors
+                .iter()
+                .filter(|error| (span.start..span.end).contains(&error.span().start))
+                .peekable();
+            let valid = inside.peek().is_none();
+            errors.extend(
+                inside
+                    .filter(|error| error.span().start < chunk_start)
+                    .cloned()
+                    .map(ParseError::Lex),
+            );
+            valid
+        });
+        self.pending_start = match parser.peek() {
+            Some(token) => (token.span.start, token.span.line, token.span.column),
+            None => {
+                let (line, column) = end_position(&self.pending, line, column);
+                (offset + self.pending.len(), line, column)
+            }
+        };
+        self.pending.drain(..self.pending_start.0 - offset);
+
+        if !errors.is_empty() {
+            return Err(ParseFailure { errors, nodes });
+        }
+        Ok(nodes)
+    }
+}
+
+impl Default for ParseSession {
+    fn default() -> Self {
+        Self::new(FileId::default(), ParserSettings::default())
+    }
+}
+
+/// Line and column right after `text`, when it starts at `line` and `column`.
+fn end_position(text: &str, line: u32, column: u32) -> (u32, u32) {
+    text.chars().fold((line, column), |(line, column), ch| {
+        if ch == '\n' {
+            (line + 1, 1)
+        } else {
+            (line, column + 1)
+        }
+    })
+}
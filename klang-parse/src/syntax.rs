@@ -0,0 +1,383 @@
+use std::{fmt, ops::Range, rc::Rc, sync::Arc};
+
+use crate::{error::ParseError, lower::lower_root, token::Token};
+use klang_ast::{id::NodeId, node::ASTNode, span::FileId};
+
+/// Kinds of the tokens and nodes of a lossless syntax tree.
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+pub enum SyntaxKind {
+    /// Spaces, tabs and line breaks.
+    Whitespace,
+    /// A `#` comment, up to the end of its line.
+    Comment,
+    /// Text the lexer could not turn into a token.
+    Unknown,
+    Fun,
+    Pub,
+    Use,
+    Import,
+    Mod,
+    Delimiter,
+    OpeningParenthesis,
+    ClosingParenthesis,
+    OpeningBrace,
+    ClosingBrace,
+    Comma,
+    PathSeparator,
+    Ident,
+    Number,
+    Operator,
+    String,
+    If,
+    Else,
+    Var,
+    In,
+    For,
+    While,
+
+    /// The whole source text.
+    Root,
+    /// `fun prototype { body }`, possibly preceded by `pub`.
+    Function,
+    /// `use prototype`.
+    Extern,
+    /// `import "path"`.
+    ImportDecl,
+    /// `mod name { items }`.
+    Module,
+    /// A name, possibly declaring an operator, and its parameter list.
+    Prototype,
+    /// `(a, b)` of a prototype.
+    ParameterList,
+    LiteralExpr,
+    VariableExpr,
+    /// A possibly qualified name followed by an argument list.
+    CallExpr,
+    /// `(a, b)` of a call.
+    ArgumentList,
+    ParenExpr,
+    UnaryExpr,
+    BinaryExpr,
+    BlockExpr,
+    IfExpr,
+    VarExpr,
+    /// `a = 1` of a `var` expression.
+    VarBinding,
+    ForExpr,
+    WhileExpr,
+    /// Tokens skipped while recovering from a parse error.
+    Error,
+}
+
+impl SyntaxKind {
+    /// Whether tokens of this kind carry no meaning for the parser.
+    pub fn is_trivia(self) -> bool {
+        matches!(
+            self,
+            SyntaxKind::Whitespace | SyntaxKind::Comment | SyntaxKind::Unknown
+        )
+    }
+
+    /// Whether nodes of this kind are expressions.
+    pub fn is_expr(self) -> bool {
+        matches!(
+            self,
+            SyntaxKind::LiteralExpr
+                | SyntaxKind::VariableExpr
+                | SyntaxKind::CallExpr
+                | SyntaxKind::ParenExpr
+                | SyntaxKind::UnaryExpr
+                | SyntaxKind::BinaryExpr
+                | SyntaxKind::BlockExpr
+                | SyntaxKind::IfExpr
+                | SyntaxKind::VarExpr
+                | SyntaxKind::ForExpr
+                | SyntaxKind::WhileExpr
+        )
+    }
+}
+
+impl From<&Token<'_>> for SyntaxKind {
+    fn from(token: &Token) -> Self {
+        match token {
+            Token::Fun => SyntaxKind::Fun,
+            Token::Pub => SyntaxKind::Pub,
+            Token::Use => SyntaxKind::Use,
+            Token::Import => SyntaxKind::Import,
+            Token::Mod => SyntaxKind::Mod,
+            Token::Delimiter => SyntaxKind::Delimiter,
+            Token::OpeningParenthesis => SyntaxKind::OpeningParenthesis,
+            Token::ClosingParenthesis => SyntaxKind::ClosingParenthesis,
+            Token::OpeningBrace => SyntaxKind::OpeningBrace,
+            Token::ClosingBrace => SyntaxKind::ClosingBrace,
+            Token::Comma => SyntaxKind::Comma,
+            Token::PathSeparator => SyntaxKind::PathSeparator,
+            Token::Ident(_) => SyntaxKind::Ident,
+            Token::Number(_) => SyntaxKind::Number,
+            Token::Operator(_) => SyntaxKind::Operator,
+            Token::String(_) => SyntaxKind::String,
+            Token::If => SyntaxKind::If,
+            Token::Else => SyntaxKind::Else,
+            Token::Var => SyntaxKind::Var,
+            Token::In => SyntaxKind::In,
+            Token::For => SyntaxKind::For,
+            Token::While => SyntaxKind::While,
+        }
+    }
+}
+
+/// A token of the green tree: its kind and exact source text.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GreenToken {
+    kind: SyntaxKind,
+    text: String,
+}
+
+impl GreenToken {
+    pub fn new(kind: SyntaxKind, text: impl Into<String>) -> Self {
+        Self {
+            kind,
+            text: text.into(),
+        }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// A node of the green tree.
+///
+/// Green nodes only know their length, not their position, so unchanged
+/// subtrees can be shared between versions of an edited tree.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GreenNode {
+    kind: SyntaxKind,
+    text_len: usize,
+    children: Vec<GreenElement>,
+}
+
+impl GreenNode {
+    pub fn new(kind: SyntaxKind, children: Vec<GreenElement>) -> Self {
+        let text_len = children.iter().map(GreenElement::text_len).sum();
+        Self {
+            kind,
+            text_len,
+            children,
+        }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    /// Length of the node's source text in bytes.
+    pub fn text_len(&self) -> usize {
+        self.text_len
+    }
+
+    pub fn children(&self) -> &[GreenElement] {
+        &self.children
+    }
+}
+
+impl fmt::Display for GreenNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for child in &self.children {
+            match child {
+                GreenElement::Node(node) => write!(f, "{node}")?,
+                GreenElement::Token(token) => write!(f, "{}", token.text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum GreenElement {
+    Node(Arc<GreenNode>),
+    Token(Arc<GreenToken>),
+}
+
+impl GreenElement {
+    pub fn text_len(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.text_len,
+            GreenElement::Token(token) => token.text.len(),
+        }
+    }
+}
+
+/// A node of the red tree, a view of a green node that knows its parent and
+/// its position in the source.
+#[derive(Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+struct NodeData {
+    green: Arc<GreenNode>,
+    parent: Option<SyntaxNode>,
+    offset: usize,
+}
+
+impl SyntaxNode {
+    /// Views `green` as the root of a tree starting at offset 0.
+    pub fn new_root(green: Arc<GreenNode>) -> Self {
+        SyntaxNode(Rc::new(NodeData {
+            green,
+            parent: None,
+            offset: 0,
+        }))
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.green.kind
+    }
+
+    pub fn green(&self) -> &Arc<GreenNode> {
+        &self.0.green
+    }
+
+    pub fn parent(&self) -> Option<SyntaxNode> {
+        self.0.parent.clone()
+    }
+
+    /// Byte range of the node's text in the source.
+    pub fn text_range(&self) -> Range<usize> {
+        self.0.offset..self.0.offset + self.0.green.text_len
+    }
+
+    /// Child nodes and tokens, trivia included, in source order.
+    pub fn children_with_tokens(&self) -> impl Iterator<Item = SyntaxElement> + '_ {
+        let mut offset = self.0.offset;
+        self.0.green.children.iter().map(move |child| {
+            let child_offset = offset;
+            offset += child.text_len();
+            match child {
+                GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                    green: green.clone(),
+                    parent: Some(self.clone()),
+                    offset: child_offset,
+                }))),
+                GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                    green: green.clone(),
+                    parent: self.clone(),
+                    offset: child_offset,
+                }),
+            }
+        })
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = SyntaxNode> + '_ {
+        self.children_with_tokens().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    /// Tokens directly below this node, trivia excluded.
+    pub fn child_tokens(&self) -> impl Iterator<Item = SyntaxToken> + '_ {
+        self.children_with_tokens().filter_map(|child| match child {
+            SyntaxElement::Token(token) if !token.kind().is_trivia() => Some(token),
+            _ => None,
+        })
+    }
+}
+
+impl PartialEq for SyntaxNode {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0.green, &other.0.green) && self.0.offset == other.0.offset
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.green)
+    }
+}
+
+impl fmt::Debug for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}@{:?}", self.kind(), self.text_range())
+    }
+}
+
+/// A token of the red tree.
+#[derive(Clone, PartialEq)]
+pub struct SyntaxToken {
+    green: Arc<GreenToken>,
+    parent: SyntaxNode,
+    offset: usize,
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> SyntaxKind {
+        self.green.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.green.text
+    }
+
+    pub fn parent(&self) -> SyntaxNode {
+        self.parent.clone()
+    }
+
+    /// Byte range of the token's text in the source.
+    pub fn text_range(&self) -> Range<usize> {
+        self.offset..self.offset + self.green.text.len()
+    }
+}
+
+impl fmt::Debug for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?}@{:?} {:?}",
+            self.kind(),
+            self.text_range(),
+            self.text()
+        )
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+/// A lossless syntax tree and the errors found while building it.
+///
+/// The tree holds every byte of the source, so printing `root` gives back the
+/// source text even when it has errors.
+#[derive(Clone, Debug)]
+pub struct SyntaxTree {
+    pub root: SyntaxNode,
+    pub errors: Vec<ParseError>,
+    /// File the source was read from.
+    pub(crate) file: FileId,
+    /// Id the parser gave its first node, taken from its settings.
+    pub(crate) first_id: NodeId,
+}
+
+impl SyntaxTree {
+    /// Lowers the tree to the AST `parse` gives for the same source and
+    /// settings, with the same spans and ids.
+    ///
+    /// Items holding errors are left out and counted in `Lowered::dropped`.
+    pub fn lower(&self) -> Lowered {
+        lower_root(&self.root, self.file, self.first_id)
+    }
+}
+
+/// The AST lowered from a syntax tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lowered {
+    pub nodes: Vec<ASTNode>,
+    /// Number of items left out because they hold errors.
+    pub dropped: usize,
+}
@@ -1,16 +1,101 @@
+use std::{borrow::Cow, fmt};
+
+use klang_ast::span::Span;
+
+/// A token of klang source text.
+///
+/// Identifiers, operators and strings borrow their text from the source they
+/// were lexed from, use `into_owned` to keep a token around longer.
 #[derive(PartialEq, Clone, Debug)]
-pub enum Token {
+pub enum Token<'src> {
     Fun,
+    Pub,
     Use,
+    Import,
+    Mod,
     Delimiter,
     OpeningParenthesis,
     ClosingParenthesis,
     OpeningBrace,
     ClosingBrace,
     Comma,
-    Ident(String),
+    /// `::`, separating the segments of a qualified name.
+    PathSeparator,
+    Ident(Cow<'src, str>),
     Number(f64),
-    Operator(String),
+    Operator(Cow<'src, str>),
+    /// A `"..."` literal, without the quotes.
+    String(Cow<'src, str>),
     If,
     Else,
+    Var,
+    In,
+    For,
+    While,
+}
+
+impl Token<'_> {
+    /// Copies borrowed source text, detaching the token from the source.
+    pub fn into_owned(self) -> Token<'static> {
+        match self {
+            Token::Fun => Token::Fun,
+            Token::Pub => Token::Pub,
+            Token::Use => Token::Use,
+            Token::Import => Token::Import,
+            Token::Mod => Token::Mod,
+            Token::Delimiter => Token::Delimiter,
+            Token::OpeningParenthesis => Token::OpeningParenthesis,
+            Token::ClosingParenthesis => Token::ClosingParenthesis,
+            Token::OpeningBrace => Token::OpeningBrace,
+            Token::ClosingBrace => Token::ClosingBrace,
+            Token::Comma => Token::Comma,
+            Token::PathSeparator => Token::PathSeparator,
+            Token::Ident(name) => Token::Ident(Cow::Owned(name.into_owned())),
+            Token::Number(value) => Token::Number(value),
+            Token::Operator(op) => Token::Operator(Cow::Owned(op.into_owned())),
+            Token::String(text) => Token::String(Cow::Owned(text.into_owned())),
+            Token::If => Token::If,
+            Token::Else => Token::Else,
+            Token::Var => Token::Var,
+            Token::In => Token::In,
+            Token::For => Token::For,
+            Token::While => Token::While,
+        }
+    }
+}
+
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Fun => write!(f, "`fun`"),
+            Token::Pub => write!(f, "`pub`"),
+            Token::Use => write!(f, "`use`"),
+            Token::Import => write!(f, "`import`"),
+            Token::Mod => write!(f, "`mod`"),
+            Token::Delimiter => write!(f, "`;`"),
+            Token::OpeningParenthesis => write!(f, "`(`"),
+            Token::ClosingParenthesis => write!(f, "`)`"),
+            Token::OpeningBrace => write!(f, "`{{`"),
+            Token::ClosingBrace => write!(f, "`}}`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::PathSeparator => write!(f, "`::`"),
+            Token::Ident(name) => write!(f, "identifier `{name}`"),
+            Token::Number(value) => write!(f, "number `{value}`"),
+            Token::Operator(op) => write!(f, "operator `{op}`"),
+            Token::String(text) => write!(f, "string {text:?}"),
+            Token::If => write!(f, "`if`"),
+            Token::Else => write!(f, "`else`"),
+            Token::Var => write!(f, "`var`"),
+            Token::In => write!(f, "`in`"),
+            Token::For => write!(f, "`for`"),
+            Token::While => write!(f, "`while`"),
+        }
+    }
+}
+
+/// A `Token` together with the location it was lexed from.
+#[derive(PartialEq, Clone, Debug)]
+pub struct SpannedToken<'src> {
+    pub token: Token<'src>,
+    pub span: Span,
 }
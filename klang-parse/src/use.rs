@@ -1,16 +1,19 @@
 use crate::{
-    parse::{Parse, PartParsingResult},
-    parse_try,
-    token::Token,
+    parse::{Parse, Parser, PartParsingResult},
+    parser::ParserSettings,
+    syntax::SyntaxKind,
 };
 use klang_ast::{function::Prototype, node::ASTNode};
 
 impl Parse<ASTNode> for Prototype {
-    fn parse(tokens: &mut Vec<Token>) -> PartParsingResult<ASTNode> {
-        tokens.pop();
-        let mut parsed_tokens = vec![Token::Use];
-        let prototype_partial_parsing = Prototype::parse(tokens);
-        let prototype = parse_try!(prototype_partial_parsing, tokens, parsed_tokens);
-        PartParsingResult::Good(ASTNode::ExternNode(prototype), parsed_tokens)
+    fn parse(parser: &mut Parser, settings: &ParserSettings) -> PartParsingResult<ASTNode> {
+        let marker = parser.start();
+        // Consume `use` keyword.
+        parser.bump();
+        let mut prototype: Prototype = Prototype::parse(parser, settings)?;
+        parser.finish(marker, SyntaxKind::Extern);
+        // the extern spans its `use` keyword too
+        prototype.span = parser.span_since(marker);
+        Ok(ASTNode::ExternNode(prototype))
     }
 }
@@ -0,0 +1,18 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Parser;
+
+/// Format klang source files in place.
+#[derive(Debug, Default, Parser)]
+pub struct Command {
+    /// Files to format. Defaults to every `.kl` file below the current directory.
+    pub files: Vec<PathBuf>,
+    /// Leave the files alone, print a diff and fail if they aren't formatted.
+    #[clap(long)]
+    pub check: bool,
+}
+
+pub(crate) fn exec(fmt_command: Command) -> Result<()> {
+    crate::ops::korc_fmt::fmt(fmt_command)
+}
@@ -1,8 +1,10 @@
+mod fmt;
 mod run;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-pub use run::Command as RunCommand;
+pub use fmt::Command as FmtCommand;
+pub use run::{AstFormat, Command as RunCommand};
 
 #[derive(Debug, Parser)]
 #[clap(name = "korc", about = "Klang Orchestrator", version)]
@@ -15,11 +17,13 @@ struct Opt {
 #[derive(Subcommand, Debug)]
 enum Korc {
     Run(RunCommand),
+    Fmt(FmtCommand),
 }
 
 pub fn run_cli() -> Result<()> {
     let opt = Opt::parse();
     match opt.command {
         Korc::Run(run_command) => run::exec(run_command),
+        Korc::Fmt(fmt_command) => fmt::exec(fmt_command),
     }
 }
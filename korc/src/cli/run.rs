@@ -1,5 +1,7 @@
+use std::path::PathBuf;
+
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
 /// Compile and run the current project.
 #[derive(Debug, Default, Parser)]
@@ -10,12 +12,31 @@ pub struct Command {
     /// Output generated AST.
     #[clap(long)]
     pub ast: bool,
+    /// Format of the AST output.
+    #[clap(long, value_enum, default_value_t)]
+    pub ast_format: AstFormat,
     /// Output generated IR.
     #[clap(long)]
     pub ir: bool,
     /// Output to file.
     #[clap(long)]
     pub file_out: bool,
+    /// Entry file to compile instead of `main.kl`, a `.json` one holding a
+    /// JSON AST.
+    #[clap(long)]
+    pub input: Option<PathBuf>,
+}
+
+/// How `--ast` writes the AST.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AstFormat {
+    /// JSON, which `--input` reads back.
+    Json,
+    /// S-expressions, showing how expressions are grouped.
+    Sexpr,
+    /// Rust debug output.
+    #[default]
+    Debug,
 }
 
 pub(crate) fn exec(run_command: Command) -> Result<()> {
@@ -0,0 +1,429 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::cli::FmtCommand;
+use anyhow::{anyhow, bail, Context, Result};
+use klang_parse::format::format_source;
+
+const KLANG_EXTENSION: &str = "kl";
+/// Unchanged lines shown around the changes of a diff.
+const DIFF_CONTEXT_LINES: usize = 3;
+
+pub fn fmt(cmd: FmtCommand) -> Result<()> {
+    let files = if cmd.files.is_empty() {
+        let mut files = Vec::new();
+        find_sources(&std::env::current_dir()?, &mut files)?;
+        files.sort();
+        files
+    } else {
+        cmd.files
+    };
+
+    let mut unformatted = 0;
+    for path in &files {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("cannot read `{}`", path.display()))?;
+        let formatted = format_source(&source).map_err(|errors| {
+            let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+            anyhow!("cannot format `{}`:\n{}", path.display(), errors.join("\n"))
+        })?;
+        if formatted == source {
+            continue;
+        }
+
+        if cmd.check {
+            print!("{}", diff(path, &source, &formatted));
+            unformatted += 1;
+        } else {
+            fs::write(path, formatted)
+                .with_context(|| format!("cannot write `{}`", path.display()))?;
+        }
+    }
+
+    if unformatted > 0 {
+        bail!("{unformatted} file(s) not formatted, run `korc fmt` to format them");
+    }
+    Ok(())
+}
+
+/// Collects the klang files below `dir`, skipping hidden directories, build
+/// output and symlinks, which could loop back to a parent directory.
+fn find_sources(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir).with_context(|| format!("cannot read `{}`", dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let file_type = entry.file_type()?;
+        if name.starts_with('.') || name == "target" || file_type.is_symlink() {
+            continue;
+        }
+        if file_type.is_dir() {
+            find_sources(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == KLANG_EXTENSION) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// A line of a diff.
+#[derive(PartialEq, Clone, Copy)]
+enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Unified diff of the lines of `path` turning `old` into `new`.
+fn diff(path: &Path, old: &str, new: &str) -> String {
+    let old: Vec<_> = old.lines().collect();
+    let new: Vec<_> = new.lines().collect();
+    let mut lines = Vec::new();
+    diff_lines(&old, &new, &mut lines);
+
+    let mut out = format!("--- {}\n+++ {}\n", path.display(), path.display());
+    let changes: Vec<_> = (0..lines.len())
+        .filter(|&index| !matches!(lines[index], DiffLine::Same(_)))
+        .collect();
+    let mut next = 0;
+    while next < changes.len() {
+        // a hunk takes the changes whose context overlaps
+        let start = changes[next].saturating_sub(DIFF_CONTEXT_LINES);
+        let mut last = changes[next];
+        while next + 1 < changes.len() && changes[next + 1] - last <= 2 * DIFF_CONTEXT_LINES {
+            next += 1;
+            last = changes[next];
+        }
+        next += 1;
+        let end = (last + DIFF_CONTEXT_LINES + 1).min(lines.len());
+
+        let old_start = lines[..start]
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Added(_)))
+            .count();
+        let new_start = lines[..start]
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Removed(_)))
+            .count();
+        let hunk = &lines[start..end];
+        let old_len = hunk
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Added(_)))
+            .count();
+        let new_len = hunk
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Removed(_)))
+            .count();
+
+        out.push_str(&format!(
+            "@@ -{},{old_len} +{},{new_len} @@\n",
+            old_start + 1,
+            new_start + 1
+        ));
+        for line in hunk {
+            let (prefix, text) = match line {
+                DiffLine::Same(text) => (' ', text),
+                DiffLine::Removed(text) => ('-', text),
+                DiffLine::Added(text) => ('+', text),
+            };
+            out.push_str(&format!("{prefix}{text}\n"));
+        }
+    }
+    out
+}
+
+/// Pushes the lines of a shortest diff turning `old` into `new` to `lines`.
+///
+/// Uses the divide and conquer variant of Myers' algorithm, taking
+/// O((N + M) D) time but only O(N + M) memory for N old and M new lines and
+/// D changes.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str], lines: &mut Vec<DiffLine<'a>>) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    lines.extend(old[..prefix].iter().copied().map(DiffLine::Same));
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old, common_end) = old.split_at(old.len() - suffix);
+    let new = &new[..new.len() - suffix];
+
+    match middle_snake(old, new) {
+        Some((x, y)) => {
+            diff_lines(&old[..x], &new[..y], lines);
+            diff_lines(&old[x..], &new[y..], lines);
+        }
+        None => {
+            lines.extend(old.iter().copied().map(DiffLine::Removed));
+            lines.extend(new.iter().copied().map(DiffLine::Added));
+        }
+    }
+    lines.extend(common_end.iter().copied().map(DiffLine::Same));
+}
+
+/// Point where a shortest path through the edit graph of `old` and `new`
+/// crosses its middle, found by searching from both ends at once. `None` if
+/// `old` and `new` have no line in common.
+///
+/// Expects `old` and `new` to differ in their first and last lines, so the
+/// point splits the diff into two smaller ones.
+fn middle_snake(old: &[&str], new: &[&str]) -> Option<(usize, usize)> {
+    if old.is_empty() || new.is_empty() {
+        return None;
+    }
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max_d = (n + m + 1) / 2;
+    // forward[offset + k] is the furthest `x` reached from the start on the
+    // diagonal k = x - y, backward[offset + k] the same from the end
+    let offset = max_d;
+    let mut forward = vec![-1; 2 * max_d as usize + 2];
+    let mut backward = forward.clone();
+    forward[offset as usize + 1] = 0;
+    backward[offset as usize + 1] = 0;
+    let delta = n - m;
+    // the searches meet going forward when the total length is odd
+    let front = delta % 2 != 0;
+    // diagonals that left the graph, no longer searched
+    let (mut forward_start, mut forward_end) = (0, 0);
+    let (mut backward_start, mut backward_end) = (0, 0);
+
+    for d in 0..max_d {
+        for k in (-d + forward_start..=d - forward_end).step_by(2) {
+            let index = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && forward[index - 1] < forward[index + 1]) {
+                forward[index + 1]
+            } else {
+                forward[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index] = x;
+            if x > n {
+                forward_end += 2;
+            } else if y > m {
+                forward_start += 2;
+            } else if front {
+                let other = offset + delta - k;
+                if other >= 0 && other < backward.len() as isize && backward[other as usize] != -1 {
+                    // the paths overlap
+                    if x >= n - backward[other as usize] {
+                        return Some((x as usize, y as usize));
+                    }
+                }
+            }
+        }
+
+        for k in (-d + backward_start..=d - backward_end).step_by(2) {
+            let index = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && backward[index - 1] < backward[index + 1]) {
+                backward[index + 1]
+            } else {
+                backward[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[(n - x - 1) as usize] == new[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[index] = x;
+            if x > n {
+                backward_end += 2;
+            } else if y > m {
+                backward_start += 2;
+            } else if !front {
+                let other = offset + delta - k;
+                if other >= 0 && other < forward.len() as isize && forward[other as usize] != -1 {
+                    let forward_x = forward[other as usize];
+                    let forward_y = forward_x - (other - offset);
+                    // the paths overlap
+                    if forward_x >= n - x {
+                        return Some((forward_x as usize, forward_y as usize));
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use klang_parse::format::format_source;
+
+    use super::{diff, diff_lines, find_sources, fmt, DiffLine};
+    use crate::cli::FmtCommand;
+
+    /// Creates an empty directory named after `test`.
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("korc-fmt-{}-{test}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Next number below `bound` of a simple pseudo-random sequence.
+    fn next_random(seed: &mut u32, bound: u32) -> u32 {
+        *seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (*seed >> 16) % bound
+    }
+
+    /// Length of the longest common subsequence of `old` and `new`.
+    fn common_len(old: &[&str], new: &[&str]) -> usize {
+        let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                common[i][j] = if old[i] == new[j] {
+                    common[i + 1][j + 1] + 1
+                } else {
+                    common[i + 1][j].max(common[i][j + 1])
+                };
+            }
+        }
+        common[0][0]
+    }
+
+    #[test]
+    fn diff_is_shortest() {
+        let mut seed = 7;
+        for _ in 0..2000 {
+            let mut lines = || -> Vec<&str> {
+                let len = next_random(&mut seed, 12);
+                (0..len)
+                    .map(|_| ["a", "b", "c", "d"][next_random(&mut seed, 4) as usize])
+                    .collect()
+            };
+            let (old, new) = (lines(), lines());
+            let mut diff = Vec::new();
+            diff_lines(&old, &new, &mut diff);
+
+            let kept = |skipped: fn(&DiffLine) -> bool| -> Vec<&str> {
+                diff.iter()
+                    .filter(|line| !skipped(line))
+                    .map(|line| match line {
+                        DiffLine::Same(text) | DiffLine::Removed(text) | DiffLine::Added(text) => {
+                            *text
+                        }
+                    })
+                    .collect()
+            };
+            assert_eq!(kept(|line| matches!(line, DiffLine::Added(_))), old);
+            assert_eq!(kept(|line| matches!(line, DiffLine::Removed(_))), new);
+            let same = diff
+                .iter()
+                .filter(|line| matches!(line, DiffLine::Same(_)))
+                .count();
+            assert_eq!(same, common_len(&old, &new), "{old:?} -> {new:?}");
+        }
+    }
+
+    #[test]
+    fn diff_large_files() {
+        let old: String = (0..3_000).map(|n| format!("line {n}\n")).collect();
+        let new: String = (0..3_000).map(|n| format!("  line {n}\n")).collect();
+        let out = diff(Path::new("big.kl"), &old, &new);
+        assert!(out.starts_with("--- big.kl\n+++ big.kl\n@@ -1,3000 +1,3000 @@\n-line 0\n"));
+        assert_eq!(out.lines().count(), 3 + 6_000);
+    }
+
+    #[test]
+    fn diff_shows_hunks() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nJ\n";
+        let expected = "--- f.kl
++++ f.kl
+@@ -1,5 +1,5 @@
+ a
+-b
++B
+ c
+ d
+ e
+@@ -7,4 +7,4 @@
+ g
+ h
+ i
+-j
++J
+";
+        assert_eq!(diff(Path::new("f.kl"), old, new), expected);
+    }
+
+    #[test]
+    fn fmt_check_fails_on_unformatted_files() {
+        let dir = temp_dir("check");
+        let path = dir.join("main.kl");
+        let source = "fun f(){1}\n";
+        fs::write(&path, source).unwrap();
+        let command = |check| FmtCommand {
+            files: vec![path.clone()],
+            check,
+        };
+
+        let error = fmt(command(true)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "1 file(s) not formatted, run `korc fmt` to format them"
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), source);
+
+        fmt(command(false)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format_source(source).unwrap()
+        );
+        fmt(command(true)).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fmt_accepts_imported_operators() {
+        let dir = temp_dir("imports");
+        fs::write(dir.join("op.kl"), "fun binary| 5 (a, b) {\n    a\n}\n").unwrap();
+        fs::write(
+            dir.join("main.kl"),
+            "import \"op.kl\"\nfun main() { 1 | 2 }",
+        )
+        .unwrap();
+
+        fmt(FmtCommand {
+            files: vec![dir.join("op.kl"), dir.join("main.kl")],
+            check: false,
+        })
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("main.kl")).unwrap(),
+            "import \"op.kl\";\n\nfun main() {\n    1 | 2\n}\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn find_sources_skips_symlinks() {
+        let dir = temp_dir("symlinks");
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.kl"), "").unwrap();
+        fs::write(dir.join("sub").join("b.kl"), "").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("sub").join("loop")).unwrap();
+        std::os::unix::fs::symlink(dir.join("a.kl"), dir.join("link.kl")).unwrap();
+
+        let mut files = Vec::new();
+        find_sources(&dir, &mut files).unwrap();
+        files.sort();
+        assert_eq!(files, vec![dir.join("a.kl"), dir.join("sub").join("b.kl")]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,7 +1,7 @@
-use crate::cli::RunCommand;
+use crate::cli::{AstFormat, RunCommand};
 use anyhow::Result;
-use inkwell::{context::Context, OptimizationLevel};
-use klang_core::{ast_to_ir, parse_to_ast};
+use inkwell::{context::Context, module::Module, OptimizationLevel};
+use klang_core::{ast_to_json, load_program, program_to_ir, SExpr, ANONYMOUS_FUNCTION_PREFIX};
 
 const KLANG_EXTENSION: &str = ".kl";
 const KLANG_ENTRY_NAME: &str = "main";
@@ -14,12 +14,18 @@ pub fn run(cmd: RunCommand) -> Result<()> {
     }
 
     let current_dir = std::env::current_dir()?;
-    let main_file_name = format!("{KLANG_ENTRY_NAME}{KLANG_EXTENSION}");
-    let main_file_path = current_dir.join(main_file_name);
-    let input_src = std::fs::read_to_string(main_file_path)?;
-    let parse_result = parse_to_ast(&input_src)?;
+    let main_file_path = match &cmd.input {
+        Some(input) => current_dir.join(input),
+        None => current_dir.join(format!("{KLANG_ENTRY_NAME}{KLANG_EXTENSION}")),
+    };
+    let program = load_program(&main_file_path)?;
+    let ast = &program.nodes;
     if cmd.ast {
-        let ast_str = format!("AST {parse_result:#?}");
+        let ast_str = match cmd.ast_format {
+            AstFormat::Json => ast_to_json(ast)?,
+            AstFormat::Sexpr => SExpr(&ast[..]).to_string(),
+            AstFormat::Debug => format!("AST {ast:#?}"),
+        };
         if cmd.file_out {
             let path = current_dir.join(KLANG_DEFAULT_AST_FILE_NAME);
             std::fs::write(path, ast_str)?;
@@ -27,9 +33,8 @@ pub fn run(cmd: RunCommand) -> Result<()> {
             println!("{ast_str}");
         }
     }
-    let (ast, _) = parse_result;
     let context = Context::create();
-    let ir_output_module_mb = ast_to_ir(&context, &ast)?;
+    let ir_output_module_mb = program_to_ir(&context, &program)?;
     let module = context
         .create_module_from_ir(ir_output_module_mb)
         .map_err(|e| anyhow::anyhow!("{e:?}"))?;
@@ -45,16 +50,55 @@ pub fn run(cmd: RunCommand) -> Result<()> {
             module.print_to_stderr()
         }
     } else {
-        let name = "main";
-
         let execution_engine = module
             .create_jit_execution_engine(OptimizationLevel::None)
             .map_err(|e| anyhow::anyhow!("{e:?}"))?;
-        let compiled_fn =
-            unsafe { execution_engine.get_function::<unsafe extern "C" fn() -> f64>(name) }?;
-        unsafe {
-            compiled_fn.call();
+
+        for name in entry_points(&module)? {
+            let compiled_fn =
+                unsafe { execution_engine.get_function::<unsafe extern "C" fn() -> f64>(&name) }?;
+            let result = unsafe { compiled_fn.call() };
+            if name != KLANG_ENTRY_NAME {
+                println!("Evaluated to {result}");
+            }
         }
     }
     Ok(())
 }
+
+/// Names of the functions to run, in order: the top-level expressions in
+/// source order, like a script, then `main` if there is one.
+fn entry_points(module: &Module) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for function in module.get_functions() {
+        let name = function.get_name().to_str()?;
+        if name.starts_with(ANONYMOUS_FUNCTION_PREFIX) {
+            names.push(name.to_string());
+        }
+    }
+    if module.get_function(KLANG_ENTRY_NAME).is_some() {
+        names.push(KLANG_ENTRY_NAME.to_string());
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod test {
+    use inkwell::context::Context;
+    use klang_core::{ast_to_ir, parse_to_ast};
+
+    use super::entry_points;
+
+    #[test]
+    fn run_expressions_then_main() {
+        let (ast, _) = parse_to_ast("fun main() { 0 } 1 fun f() { 2 } f() 3").unwrap();
+        let context = Context::create();
+        let module = context
+            .create_module_from_ir(ast_to_ir(&context, &ast).unwrap())
+            .unwrap();
+        assert_eq!(
+            entry_points(&module).unwrap(),
+            vec!["__anon_expr.0", "__anon_expr.1", "__anon_expr.2", "main"]
+        );
+    }
+}
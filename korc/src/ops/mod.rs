@@ -1 +1,2 @@
+pub mod korc_fmt;
 pub mod korc_run;